@@ -0,0 +1,392 @@
+use bevy::{
+    prelude::*,
+    ecs::entity::{EntityMapper, MapEntities},
+    utils::HashMap
+};
+use bevy_rapier3d::prelude::*;
+use bevy_replicon::{prelude::*, client::ClientSet};
+use serde::{Serialize, Deserialize};
+use client_builder::Client;
+use super::{
+    *,
+    config::*,
+    network_rigidbody::*,
+    client_authority::*,
+    rate_limit::*
+};
+
+// client holding authority over a ball, None when held by server.
+// the holder predicts or simulates the body depending on its mode
+#[derive(Component, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct NetworkAuthority(Option<ClientId>);
+
+impl NetworkAuthority {
+    #[inline]
+    pub fn new(holder: Option<ClientId>) -> Self {
+        Self(holder)
+    }
+
+    #[inline]
+    pub fn holder(&self) -> Option<ClientId> {
+        self.0
+    }
+
+    #[inline]
+    pub fn is_held_by(&self, client_id: ClientId) -> bool {
+        self.0 == Some(client_id)
+    }
+
+    #[inline]
+    pub fn is_held_by_local(&self, client: &Client) -> bool {
+        self.0.is_some_and(|c| c.get() == client.id())
+    }
+}
+
+#[derive(Event, Serialize, Deserialize, Clone)]
+pub struct NetworkAuthorityRequest {
+    pub entity: Entity,
+    // server fixed tick the claiming client was predicting
+    pub claim_tick: u32
+}
+
+impl MapEntities for NetworkAuthorityRequest {
+    fn map_entities<M: EntityMapper>(&mut self, entity_mapper: &mut M) {
+        self.entity = entity_mapper.map_entity(self.entity);
+    }
+}
+
+#[derive(Event, Serialize, Deserialize, Clone, Copy, Debug)]
+pub enum NetworkAuthorityResponse {
+    Granted(Entity),
+    Denied(Entity),
+    // sent to the previous holder when authority is handed to another client
+    Revoked(Entity)
+}
+
+impl NetworkAuthorityResponse {
+    #[inline]
+    pub fn entity(&self) -> Entity {
+        match *self {
+            NetworkAuthorityResponse::Granted(e)
+            | NetworkAuthorityResponse::Denied(e)
+            | NetworkAuthorityResponse::Revoked(e) => e
+        }
+    }
+}
+
+impl MapEntities for NetworkAuthorityResponse {
+    fn map_entities<M: EntityMapper>(&mut self, entity_mapper: &mut M) {
+        match self {
+            NetworkAuthorityResponse::Granted(e)
+            | NetworkAuthorityResponse::Denied(e)
+            | NetworkAuthorityResponse::Revoked(e) => *e = entity_mapper.map_entity(*e)
+        }
+    }
+}
+
+// server only, authority is not handed over again before this fixed tick
+#[derive(Component, Default)]
+pub struct AuthorityCooldown(u32);
+
+impl AuthorityCooldown {
+    #[inline]
+    pub fn is_active(&self, tick: u32) -> bool {
+        (self.0.wrapping_sub(tick) as i32) > 0
+    }
+}
+
+// client only, a request was sent and is waiting for the response
+#[derive(Component)]
+pub struct AuthorityRequestPending;
+
+#[derive(Resource)]
+pub struct AuthorityConfig {
+    pub handoff_cooldown_sec: f32,
+    pub max_claim_age_ticks: u32,
+    pub max_claim_lead_ticks: u32
+}
+
+impl Default for AuthorityConfig {
+    fn default() -> Self {
+        Self{
+            handoff_cooldown_sec: AUTHORITY_HANDOFF_COOLDOWN_SEC,
+            max_claim_age_ticks: (AUTHORITY_CLAIM_MAX_AGE_SEC * PHYSICS_FIXED_TICK_RATE) as u32,
+            max_claim_lead_ticks: AUTHORITY_CLAIM_MAX_LEAD_TICKS
+        }
+    }
+}
+
+impl AuthorityConfig {
+    // claim tick within the plausible window around server tick,
+    // None when too old
+    pub fn plausible_claim_tick(&self, claim_tick: u32, tick: u32) -> Option<u32> {
+        let ahead = claim_tick.wrapping_sub(tick) as i32;
+        if ahead < -(self.max_claim_age_ticks as i32) {
+            return None;
+        }
+        Some(tick.wrapping_add(ahead.min(self.max_claim_lead_ticks as i32) as u32))
+    }
+}
+
+// holder keeps authority if it claims too, otherwise the earliest claim wins
+// and claims of the same tick go to the lowest client id.
+// nobody wins while the cooldown of the last handoff is active
+pub fn arbitrate_authority(
+    holder: Option<ClientId>,
+    claims: &[(ClientId, u32)],
+    tick: u32,
+    is_cooldown_active: bool
+) -> Option<ClientId> {
+    if let Some(holder) = holder.filter(|h| claims.iter().any(|(c, _)| c == h)) {
+        return Some(holder);
+    }
+    if is_cooldown_active {
+        return None;
+    }
+
+    claims.iter()
+    .min_by_key(|(client_id, claim_tick)| (
+        claim_tick.wrapping_sub(tick) as i32,
+        client_id.get()
+    ))
+    .map(|(client_id, _)| *client_id)
+}
+
+pub struct AuthorityPlugin;
+
+impl Plugin for AuthorityPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<AuthorityConfig>()
+        .replicate::<NetworkAuthority>()
+        .add_mapped_client_event::<NetworkAuthorityRequest>(ChannelKind::Ordered)
+        .add_mapped_server_event::<NetworkAuthorityResponse>(ChannelKind::Ordered)
+        .add_systems(PreUpdate,
+            handle_authority_request_system
+            .after(ServerSet::Receive)
+            .run_if(server_running)
+        )
+        .add_systems(PreUpdate,
+            handle_authority_response_system
+            .after(ClientSet::Receive)
+            .run_if(client_connected)
+        )
+        .add_systems(FixedUpdate,
+            request_authority_on_contact_system
+            .after(AFTER_PHYSICS_SET)
+            .run_if(client_connected)
+        );
+    }
+}
+
+// claims of the same body are resolved together so that
+// conflicting claims within a frame get a single winner
+#[allow(clippy::type_complexity)]
+fn handle_authority_request_system(
+    mut query: Query<(
+        &mut NetworkAuthority,
+        &mut NetworkRigidBody,
+        &mut LastInteraction,
+        &mut AuthorityCooldown,
+        Option<&mut AuthorityValidation>
+    )>,
+    mut requests: EventReader<FromClient<NetworkAuthorityRequest>>,
+    mut responses: EventWriter<ToClients<NetworkAuthorityResponse>>,
+    mut limits: ClientLimits,
+    fixed_tick: Res<FixedTick>,
+    config: Res<AuthorityConfig>
+) {
+    let tick = fixed_tick.get();
+    let mut claims = HashMap::<Entity, Vec<(ClientId, u32)>>::new();
+    for FromClient { client_id, event } in requests.read() {
+        let claim_tick = if limits.allow(*client_id, RateLimitedEvent::AuthorityRequest) {
+            config.plausible_claim_tick(event.claim_tick, tick)
+        } else {
+            None
+        };
+        // denied so that the client can claim again later
+        let Some(claim_tick) = claim_tick else {
+            responses.send(ToClients {
+                mode: SendMode::Direct(*client_id),
+                event: NetworkAuthorityResponse::Denied(event.entity)
+            });
+            continue;
+        };
+
+        claims.entry(event.entity)
+        .or_default()
+        .push((*client_id, claim_tick));
+    }
+
+    for (e, claims) in claims {
+        let winner = match query.get(e) {
+            Ok((authority, _, _, cooldown, _)) => arbitrate_authority(
+                authority.holder(),
+                &claims,
+                tick,
+                cooldown.is_active(tick)
+            ),
+            Err(_) => None
+        };
+
+        for (client_id, _) in claims.iter() {
+            let response = if Some(*client_id) == winner {
+                NetworkAuthorityResponse::Granted(e)
+            } else {
+                NetworkAuthorityResponse::Denied(e)
+            };
+            responses.send(ToClients {
+                mode: SendMode::Direct(*client_id),
+                event: response
+            });
+        }
+
+        let Some(winner) = winner else {
+            continue;
+        };
+        let Ok((mut authority, mut net_rb, mut last, mut cooldown, validation))
+        = query.get_mut(e) else {
+            continue;
+        };
+        // claim counts as an interaction, also when the holder kept it
+        last.touch(tick);
+        let previous = authority.holder();
+        if previous == Some(winner) {
+            continue;
+        }
+
+        if let Some(previous) = previous {
+            responses.send(ToClients {
+                mode: SendMode::Direct(previous),
+                event: NetworkAuthorityResponse::Revoked(e)
+            });
+        }
+
+        *authority = NetworkAuthority::new(Some(winner));
+        cooldown.0 = tick.wrapping_add(
+            (config.handoff_cooldown_sec * PHYSICS_FIXED_TICK_RATE) as u32
+        );
+        if net_rb.mode() == NetworkRigidBodyMode::ServerSimulation {
+            *net_rb = net_rb.with_mode(NetworkRigidBodyMode::ClientPrediction);
+        }
+        // new holder streams from where the body is now
+        if let Some(mut validation) = validation {
+            *validation = AuthorityValidation::new(net_rb.translation(), tick);
+        }
+
+        info!("authority of entity: {e:?} handed from: {previous:?} to: {winner:?}");
+    }
+}
+
+// balls held by this client claim the balls they touch
+#[allow(clippy::too_many_arguments)]
+fn request_authority_on_contact_system(
+    mut commands: Commands,
+    query: Query<(Entity, &NetworkAuthority), With<NetworkFireBall>>,
+    pending: Query<(), With<AuthorityRequestPending>>,
+    context: Res<RapierContext>,
+    mut requests: EventWriter<NetworkAuthorityRequest>,
+    fixed_tick: Res<FixedTick>,
+    latest_tick: Res<LatestNetworkTick>,
+    client: Res<Client>
+) {
+    let local_tick = fixed_tick.get();
+    let claim_tick = latest_tick.to_server_tick(local_tick)
+    .unwrap_or_else(|| latest_tick.estimated_fixed_tick(local_tick));
+    let mut claimed = Vec::new();
+
+    for (e, authority) in query.iter() {
+        if !authority.is_held_by_local(&client) {
+            continue;
+        }
+
+        for pair in context.contact_pairs_with(e) {
+            if !pair.has_any_active_contacts() {
+                continue;
+            }
+
+            let other = if pair.collider1() == e {
+                pair.collider2()
+            } else {
+                pair.collider1()
+            };
+            let Ok((_, other_authority)) = query.get(other) else {
+                continue;
+            };
+            if other_authority.is_held_by_local(&client)
+            || pending.contains(other)
+            || claimed.contains(&other) {
+                continue;
+            }
+
+            requests.send(NetworkAuthorityRequest{
+                entity: other,
+                claim_tick
+            });
+            commands.entity(other)
+            .insert(AuthorityRequestPending);
+            claimed.push(other);
+        }
+    }
+}
+
+// setup of the ball follows replicated NetworkAuthority,
+// responses only settle pending requests
+fn handle_authority_response_system(
+    mut commands: Commands,
+    mut responses: EventReader<NetworkAuthorityResponse>
+) {
+    for response in responses.read() {
+        let e = response.entity();
+        if let Some(mut entity) = commands.get_entity(e) {
+            entity.remove::<AuthorityRequestPending>();
+        }
+
+        info!("authority response: {response:?}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn earliest_claim_wins_and_ties_go_to_lowest_id() {
+        let a = ClientId::new(1);
+        let b = ClientId::new(2);
+
+        assert_eq!(arbitrate_authority(None, &[(b, 10), (a, 11)], 12, false), Some(b));
+        assert_eq!(arbitrate_authority(None, &[(b, 10), (a, 10)], 12, false), Some(a));
+        // claims across wrapping ticks
+        assert_eq!(arbitrate_authority(None, &[(a, 1), (b, u32::MAX)], 2, false), Some(b));
+    }
+
+    #[test]
+    fn holder_keeps_authority_and_cooldown_blocks_handoff() {
+        let a = ClientId::new(1);
+        let b = ClientId::new(2);
+
+        assert_eq!(arbitrate_authority(Some(b), &[(a, 5), (b, 10)], 12, false), Some(b));
+        assert_eq!(arbitrate_authority(Some(b), &[(a, 5)], 12, true), None);
+        assert_eq!(arbitrate_authority(Some(b), &[(a, 5)], 12, false), Some(a));
+
+        let cooldown = AuthorityCooldown(20);
+        assert!(cooldown.is_active(19));
+        assert!(!cooldown.is_active(20));
+    }
+
+    #[test]
+    fn implausible_claim_ticks_are_clamped_or_denied() {
+        let config = AuthorityConfig::default();
+        let tick = 1000;
+
+        assert_eq!(config.plausible_claim_tick(tick + 1, tick), Some(tick + 1));
+        assert_eq!(
+            config.plausible_claim_tick(tick + 500, tick), 
+            Some(tick + config.max_claim_lead_ticks)
+        );
+        let oldest = tick - config.max_claim_age_ticks;
+        assert_eq!(config.plausible_claim_tick(oldest, tick), Some(oldest));
+        assert_eq!(config.plausible_claim_tick(oldest - 1, tick), None);
+        assert_eq!(config.plausible_claim_tick(0, tick), None);
+    }
+}
@@ -0,0 +1,403 @@
+use bevy::{
+    prelude::*,
+    ecs::entity::{EntityMapper, MapEntities}
+};
+use bevy_rapier3d::prelude::*;
+use bevy_replicon::{prelude::*, client::ClientSet};
+use serde::{Serialize, Deserialize};
+use client_builder::Client;
+use super::{
+    *,
+    config::*,
+    network_rigidbody::*,
+    authority::*,
+    rate_limit::*
+};
+
+// streamed by the holder of a client authoritative ball every fixed tick
+#[derive(Event, Serialize, Deserialize, Clone)]
+pub struct NetworkAuthorityState {
+    pub entity: Entity,
+    // local fixed tick of the client
+    pub tick: u32,
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub velocity: Vec3,
+    pub angular_velocity: Vec3
+}
+
+impl MapEntities for NetworkAuthorityState {
+    fn map_entities<M: EntityMapper>(&mut self, entity_mapper: &mut M) {
+        self.entity = entity_mapper.map_entity(self.entity);
+    }
+}
+
+// sent to the holder when its state is rejected,
+// last state server accepted from it
+#[derive(Event, Serialize, Deserialize, Clone)]
+pub struct NetworkAuthorityCorrection {
+    pub entity: Entity,
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub velocity: Vec3,
+    pub angular_velocity: Vec3
+}
+
+impl MapEntities for NetworkAuthorityCorrection {
+    fn map_entities<M: EntityMapper>(&mut self, entity_mapper: &mut M) {
+        self.entity = entity_mapper.map_entity(self.entity);
+    }
+}
+
+// client only, the body is simulated locally and streamed to server
+#[derive(Component)]
+pub struct LocalAuthority;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthorityRejection {
+    NotOwner,
+    NotClientAuthority,
+    NonFinite,
+    Stale,
+    Speed,
+    AngularSpeed,
+    Teleport
+}
+
+impl AuthorityRejection {
+    // stale states are only reordered by the unreliable stream,
+    // and states of non holders are fixed by replication
+    #[inline]
+    pub fn needs_correction(&self) -> bool {
+        matches!(
+            self, 
+            AuthorityRejection::NonFinite
+            | AuthorityRejection::Speed
+            | AuthorityRejection::AngularSpeed
+            | AuthorityRejection::Teleport
+        )
+    }
+}
+
+#[derive(Resource)]
+pub struct ClientAuthorityConfig {
+    pub max_speed: f32,
+    pub max_angular_speed: f32,
+    pub teleport_tolerance: f32,
+    pub tick_slack: u32,
+    pub correction_interval_ticks: u32
+}
+
+impl Default for ClientAuthorityConfig {
+    fn default() -> Self {
+        Self{
+            max_speed: CLIENT_AUTHORITY_MAX_SPEED,
+            max_angular_speed: CLIENT_AUTHORITY_MAX_ANGULAR_SPEED,
+            teleport_tolerance: CLIENT_AUTHORITY_TELEPORT_TOLERANCE,
+            tick_slack: CLIENT_AUTHORITY_TICK_SLACK,
+            correction_interval_ticks: (CLIENT_AUTHORITY_CORRECTION_INTERVAL_SEC 
+            * PHYSICS_FIXED_TICK_RATE) as u32
+        }
+    }
+}
+
+// server only, last state accepted from the holder
+#[derive(Component)]
+pub struct AuthorityValidation {
+    translation: Vec3,
+    client_tick: Option<u32>,
+    server_tick: u32,
+    rejected: u32,
+    corrected_tick: Option<u32>
+}
+
+impl AuthorityValidation {
+    #[inline]
+    pub fn new(translation: Vec3, server_tick: u32) -> Self {
+        Self{
+            translation,
+            client_tick: None,
+            server_tick,
+            rejected: 0,
+            corrected_tick: None
+        }
+    }
+
+    #[inline]
+    pub fn rejected(&self) -> u32 {
+        self.rejected
+    }
+
+    pub fn validate(
+        &self, 
+        state: &NetworkAuthorityState,
+        server_tick: u32,
+        config: &ClientAuthorityConfig
+    ) -> Result<(), AuthorityRejection> {
+        if !state.translation.is_finite()
+        || !state.rotation.is_finite()
+        || !state.velocity.is_finite()
+        || !state.angular_velocity.is_finite() {
+            return Err(AuthorityRejection::NonFinite);
+        }
+
+        // client ticks can not run ahead of server ticks beyond slack
+        let server_elapsed = server_tick.wrapping_sub(self.server_tick)
+        .saturating_add(config.tick_slack);
+        let elapsed = match self.client_tick {
+            Some(last) => {
+                let elapsed = state.tick.wrapping_sub(last) as i32;
+                if elapsed <= 0 {
+                    return Err(AuthorityRejection::Stale);
+                }
+                (elapsed as u32).min(server_elapsed)
+            }
+            None => server_elapsed
+        };
+
+        if state.velocity.length() > config.max_speed {
+            return Err(AuthorityRejection::Speed);
+        }
+        if state.angular_velocity.length() > config.max_angular_speed {
+            return Err(AuthorityRejection::AngularSpeed);
+        }
+
+        let allowed = config.max_speed * elapsed as f32 * PHYSICS_FIXED_TICK_DELTA
+        + config.teleport_tolerance;
+        if state.translation.distance(self.translation) > allowed {
+            return Err(AuthorityRejection::Teleport);
+        }
+        Ok(())
+    }
+
+    // false while a correction sent earlier may still be in flight
+    fn try_correct(&mut self, server_tick: u32, interval_ticks: u32) -> bool {
+        if self.corrected_tick
+        .is_some_and(|t| server_tick.wrapping_sub(t) < interval_ticks) {
+            return false;
+        }
+        self.corrected_tick = Some(server_tick);
+        true
+    }
+
+    fn accept(&mut self, state: &NetworkAuthorityState, server_tick: u32) {
+        self.translation = state.translation;
+        self.client_tick = Some(state.tick);
+        self.server_tick = server_tick;
+    }
+}
+
+pub struct ClientAuthorityPlugin;
+
+impl Plugin for ClientAuthorityPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ClientAuthorityConfig>()
+        .add_mapped_client_event::<NetworkAuthorityState>(ChannelKind::Unreliable)
+        .add_mapped_server_event::<NetworkAuthorityCorrection>(ChannelKind::Ordered)
+        .add_systems(PreUpdate, 
+            validate_authority_state_system
+            .after(ServerSet::Receive)
+            .run_if(server_running)
+        )
+        .add_systems(PreUpdate, 
+            apply_authority_correction_system
+            .after(ClientSet::Receive)
+            .run_if(client_connected)
+        )
+        .add_systems(FixedUpdate, 
+            send_authority_state_system
+            .after(AFTER_PHYSICS_SET)
+            .run_if(client_connected)
+        );
+    }
+}
+
+fn send_authority_state_system(
+    query: Query<(
+        Entity,
+        &Transform,
+        &Velocity,
+        Option<&Sleeping>
+    ), 
+        With<LocalAuthority>
+    >,
+    mut states: EventWriter<NetworkAuthorityState>,
+    fixed_tick: Res<FixedTick>
+) {
+    for (e, transform, vel, sleeping) in query.iter() {
+        if sleeping.is_some_and(|s| s.sleeping) {
+            continue;
+        }
+
+        states.send(NetworkAuthorityState{
+            entity: e,
+            tick: fixed_tick.get(),
+            translation: transform.translation,
+            rotation: transform.rotation,
+            velocity: vel.linvel,
+            angular_velocity: vel.angvel
+        });
+    }
+}
+
+// accepted states are written to NetworkRigidBody,
+// which replicates them to the other clients
+fn validate_authority_state_system(
+    mut query: Query<(
+        &NetworkAuthority,
+        &mut NetworkRigidBody,
+        &mut Transform,
+        &mut AuthorityValidation
+    )>,
+    mut states: EventReader<FromClient<NetworkAuthorityState>>,
+    mut corrections: EventWriter<ToClients<NetworkAuthorityCorrection>>,
+    mut limits: ClientLimits,
+    fixed_tick: Res<FixedTick>,
+    config: Res<ClientAuthorityConfig>
+) {
+    for FromClient { client_id, event } in states.read() {
+        if !limits.allow(*client_id, RateLimitedEvent::AuthorityState) {
+            continue;
+        }
+
+        let Ok((authority, mut net_rb, mut transform, mut validation)) 
+        = query.get_mut(event.entity) else {
+            continue;
+        };
+
+        let result = if !authority.is_held_by(*client_id) {
+            Err(AuthorityRejection::NotOwner)
+        } else if !matches!(*net_rb, NetworkRigidBody::ClientAuthority { .. }) {
+            Err(AuthorityRejection::NotClientAuthority)
+        } else {
+            validation.validate(event, fixed_tick.get(), &config)
+        };
+
+        if let Err(rejection) = result {
+            validation.rejected += 1;
+            // logged once per correction, rejections between are expected in flight
+            if rejection.needs_correction() 
+            && validation.try_correct(fixed_tick.get(), config.correction_interval_ticks) {
+                warn!(
+                    "rejected state of entity: {:?} from client: {client_id:?} reason: {rejection:?} rejected: {}",
+                    event.entity,
+                    validation.rejected
+                );
+                corrections.send(ToClients {
+                    mode: SendMode::Direct(*client_id),
+                    event: NetworkAuthorityCorrection{
+                        entity: event.entity,
+                        translation: net_rb.translation(),
+                        rotation: net_rb.rotation(),
+                        velocity: net_rb.velocity().unwrap_or_default(),
+                        angular_velocity: net_rb.angular_velocity().unwrap_or_default()
+                    }
+                });
+            }
+            continue;
+        }
+
+        validation.accept(event, fixed_tick.get());
+        let rotation = align_hemisphere(event.rotation, net_rb.rotation());
+        *net_rb = NetworkRigidBody::ClientAuthority { 
+            tick: fixed_tick.get(), 
+            translation: event.translation, 
+            rotation, 
+            velocity: event.velocity, 
+            angular_velocity: event.angular_velocity 
+        };
+        transform.translation = event.translation;
+        transform.rotation = event.rotation;
+    }
+}
+
+// holder keeps simulating from the state server accepted last
+fn apply_authority_correction_system(
+    mut query: Query<(&mut Transform, &mut Velocity), With<LocalAuthority>>,
+    mut corrections: EventReader<NetworkAuthorityCorrection>
+) {
+    for correction in corrections.read() {
+        let Ok((mut transform, mut vel)) = query.get_mut(correction.entity) else {
+            continue;
+        };
+
+        transform.translation = correction.translation;
+        transform.rotation = correction.rotation;
+        vel.linvel = correction.velocity;
+        vel.angvel = correction.angular_velocity;
+        warn!("state of entity: {:?} corrected by server", correction.entity);
+    }
+}
+
+// whether the local client simulates this ball
+#[inline]
+pub fn is_local_authority(net_rb: &NetworkRigidBody, authority: &NetworkAuthority, client: &Client) -> bool {
+    matches!(net_rb, NetworkRigidBody::ClientAuthority { .. })
+    && authority.is_held_by_local(client)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(tick: u32, translation: Vec3, velocity: Vec3) -> NetworkAuthorityState {
+        NetworkAuthorityState{
+            entity: Entity::PLACEHOLDER,
+            tick,
+            translation,
+            rotation: Quat::IDENTITY,
+            velocity,
+            angular_velocity: Vec3::ZERO
+        }
+    }
+
+    #[test]
+    fn accepts_plausible_states() {
+        let config = ClientAuthorityConfig::default();
+        let mut validation = AuthorityValidation::new(BALL_SPAWN_POSITION, 0);
+
+        let first = state(100, BALL_SPAWN_POSITION + Vec3::Y * 0.1, INITIAL_VELOCITY);
+        assert_eq!(validation.validate(&first, 1, &config), Ok(()));
+        validation.accept(&first, 1);
+
+        let second = state(101, first.translation + Vec3::Y * 0.15, INITIAL_VELOCITY);
+        assert_eq!(validation.validate(&second, 2, &config), Ok(()));
+    }
+
+    #[test]
+    fn rejects_stale_fast_and_teleporting_states() {
+        let config = ClientAuthorityConfig::default();
+        let mut validation = AuthorityValidation::new(BALL_SPAWN_POSITION, 0);
+        let first = state(100, BALL_SPAWN_POSITION, Vec3::ZERO);
+        validation.accept(&first, 0);
+
+        assert_eq!(
+            validation.validate(&state(100, BALL_SPAWN_POSITION, Vec3::ZERO), 1, &config),
+            Err(AuthorityRejection::Stale)
+        );
+        assert_eq!(
+            validation.validate(&state(101, BALL_SPAWN_POSITION, Vec3::X * 1000.0), 1, &config),
+            Err(AuthorityRejection::Speed)
+        );
+        // client tick claims a long gap, bounded by server ticks
+        assert_eq!(
+            validation.validate(&state(10000, BALL_SPAWN_POSITION + Vec3::X * 50.0, Vec3::ZERO), 1, &config),
+            Err(AuthorityRejection::Teleport)
+        );
+        assert_eq!(
+            validation.validate(&state(101, Vec3::NAN, Vec3::ZERO), 1, &config),
+            Err(AuthorityRejection::NonFinite)
+        );
+    }
+
+    #[test]
+    fn corrections_wait_for_interval() {
+        let mut validation = AuthorityValidation::new(BALL_SPAWN_POSITION, 0);
+
+        assert!(validation.try_correct(10, 5));
+        assert!(!validation.try_correct(14, 5));
+        assert!(validation.try_correct(15, 5));
+        assert!(!AuthorityRejection::Stale.needs_correction());
+        assert!(AuthorityRejection::Teleport.needs_correction());
+    }
+}
@@ -0,0 +1,295 @@
+use std::collections::VecDeque;
+use bevy::prelude::*;
+use bevy_replicon::{prelude::*, client::ClientSet};
+use serde::{Serialize, Deserialize};
+use super::{
+    config::*,
+    network_rigidbody::*,
+    dilation::*,
+    rate_limit::*
+};
+
+#[derive(Event, Serialize, Deserialize)]
+pub struct NetworkPing {
+    // real time of the client, echoed back by server
+    pub client_time: f64
+}
+
+#[derive(Event, Serialize, Deserialize)]
+pub struct NetworkPong {
+    pub client_time: f64,
+    // server fixed tick and the fraction of the next one elapsed when answered
+    pub server_tick: u32,
+    pub server_overstep: f64
+}
+
+#[derive(Resource)]
+pub struct ClockConfig {
+    pub interval_sec: f64,
+    pub samples: usize,
+    pub min_samples: usize,
+    pub rtt_smoothing: f64,
+    pub lead_margin_ticks: u32,
+    pub resync_threshold_ticks: u32,
+    pub dilated_resync_threshold_ticks: u32
+}
+
+impl Default for ClockConfig {
+    fn default() -> Self {
+        Self{
+            interval_sec: CLOCK_SYNC_INTERVAL_SEC,
+            samples: CLOCK_SYNC_SAMPLES,
+            min_samples: CLOCK_SYNC_MIN_SAMPLES,
+            rtt_smoothing: CLOCK_RTT_SMOOTHING,
+            lead_margin_ticks: PREDICTION_LEAD_MARGIN_TICKS,
+            resync_threshold_ticks: PREDICTION_RESYNC_THRESHOLD_TICKS,
+            dilated_resync_threshold_ticks: PREDICTION_DILATED_RESYNC_THRESHOLD_TICKS
+        }
+    }
+}
+
+impl ClockConfig {
+    // dilation speeds up or slows down local ticks on purpose, which
+    // drifts them from the clock. re-mapping then would undo it
+    #[inline]
+    pub fn resync_threshold(&self, dilation: &TickDilation) -> u32 {
+        if dilation.get() != 0.0 {
+            self.dilated_resync_threshold_ticks
+        } else {
+            self.resync_threshold_ticks
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct ClockSample {
+    rtt: f64,
+    offset: f64
+}
+
+// client only, server time estimated from ping pong exchanges
+#[derive(Resource, Default)]
+pub struct ServerClock {
+    samples: VecDeque<ClockSample>,
+    rtt: f64,
+    rtt_deviation: f64,
+    offset: f64,
+    synced: bool
+}
+
+impl ServerClock {
+    #[inline]
+    pub fn is_synced(&self) -> bool {
+        self.synced
+    }
+
+    // smoothed round trip time in seconds
+    #[inline]
+    pub fn rtt(&self) -> f64 {
+        self.rtt
+    }
+
+    #[inline]
+    pub fn rtt_deviation(&self) -> f64 {
+        self.rtt_deviation
+    }
+
+    // server fixed ticks ahead of local fixed ticks
+    #[inline]
+    pub fn offset(&self) -> f64 {
+        self.offset
+    }
+
+    // server fixed tick running at the same time as local tick
+    #[inline]
+    pub fn estimated_tick(&self, local_tick: u32) -> u32 {
+        local_tick.wrapping_add(self.offset.round() as i32 as u32)
+    }
+
+    // ticks inputs have to be ahead of server to arrive in time
+    #[inline]
+    pub fn lead_ticks(&self, margin_ticks: u32) -> u32 {
+        let one_way = self.rtt * 0.5 + self.rtt_deviation;
+        (one_way * PHYSICS_FIXED_TICK_RATE as f64).ceil() as u32 + margin_ticks
+    }
+
+    // server fixed tick the local tick is predicting
+    #[inline]
+    pub fn target_tick(&self, local_tick: u32, margin_ticks: u32) -> u32 {
+        self.estimated_tick(local_tick)
+        .wrapping_add(self.lead_ticks(margin_ticks))
+    }
+
+    pub fn add_sample(&mut self, rtt: f64, offset: f64, config: &ClockConfig) {
+        if self.samples.is_empty() {
+            self.rtt = rtt;
+            self.rtt_deviation = rtt * 0.5;
+        } else {
+            let error = rtt - self.rtt;
+            self.rtt += config.rtt_smoothing * error;
+            self.rtt_deviation += config.rtt_smoothing * (error.abs() - self.rtt_deviation);
+        }
+
+        self.samples.push_back(ClockSample{ rtt, offset });
+        while self.samples.len() > config.samples {
+            self.samples.pop_front();
+        }
+
+        if let Some(best) = self.samples.iter()
+        .min_by(|a, b| a.rtt.total_cmp(&b.rtt)) {
+            self.offset = best.offset;
+        }
+        self.synced |= self.samples.len() >= config.min_samples;
+    }
+}
+
+pub struct ClockSyncPlugin;
+
+impl Plugin for ClockSyncPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ClockConfig>()
+        .init_resource::<ServerClock>()
+        .add_client_event::<NetworkPing>(ChannelKind::Unreliable)
+        .add_server_event::<NetworkPong>(ChannelKind::Unreliable)
+        .add_systems(PreUpdate,
+            handle_ping_system
+            .after(ServerSet::Receive)
+            .run_if(server_running)
+        )
+        .add_systems(PreUpdate, (
+            receive_pong_system,
+            update_prediction_offset_system
+            .after(update_latest_network_tick_system),
+            send_ping_system
+        ).chain(
+        ).after(ClientSet::Receive
+        ).run_if(client_connected));
+    }
+}
+
+fn handle_ping_system(
+    mut pings: EventReader<FromClient<NetworkPing>>,
+    mut pongs: EventWriter<ToClients<NetworkPong>>,
+    mut limits: ClientLimits,
+    fixed_tick: Res<FixedTick>,
+    fixed_time: Res<Time<Fixed>>
+) {
+    for FromClient { client_id, event } in pings.read() {
+        if !limits.allow(*client_id, RateLimitedEvent::Ping) {
+            continue;
+        }
+
+        pongs.send(ToClients {
+            mode: SendMode::Direct(*client_id),
+            event: NetworkPong{
+                client_time: event.client_time,
+                server_tick: fixed_tick.get(),
+                server_overstep: fixed_time.overstep_fraction_f64()
+            }
+        });
+    }
+}
+
+fn send_ping_system(
+    mut pings: EventWriter<NetworkPing>,
+    mut last_sent: Local<Option<f64>>,
+    time: Res<Time<Real>>,
+    config: Res<ClockConfig>
+) {
+    let now = time.elapsed_seconds_f64();
+    if last_sent.is_some_and(|t| now - t < config.interval_sec) {
+        return;
+    }
+
+    pings.send(NetworkPing{ client_time: now });
+    *last_sent = Some(now);
+}
+
+// server was at server tick half a round trip ago
+fn receive_pong_system(
+    mut pongs: EventReader<NetworkPong>,
+    mut clock: ResMut<ServerClock>,
+    fixed_tick: Res<FixedTick>,
+    fixed_time: Res<Time<Fixed>>,
+    time: Res<Time<Real>>,
+    config: Res<ClockConfig>
+) {
+    let now = time.elapsed_seconds_f64();
+    for pong in pongs.read() {
+        let rtt = now - pong.client_time;
+        if rtt < 0.0 {
+            continue;
+        }
+
+        let offset = pong.server_tick.wrapping_sub(fixed_tick.get()) as i32 as f64
+        + pong.server_overstep
+        - fixed_time.overstep_fraction_f64()
+        + rtt * 0.5 * PHYSICS_FIXED_TICK_RATE as f64;
+        clock.add_sample(rtt, offset, &config);
+    }
+}
+
+// predicted local ticks simulate the target tick of server,
+// re-mapped only on large drift since it invalidates prediction history
+fn update_prediction_offset_system(
+    clock: Res<ServerClock>,
+    mut latest_tick: ResMut<LatestNetworkTick>,
+    fixed_tick: Res<FixedTick>,
+    dilation: Res<TickDilation>,
+    config: Res<ClockConfig>
+) {
+    if !clock.is_synced() {
+        return;
+    }
+
+    let local_tick = fixed_tick.get();
+    let offset = local_tick.wrapping_sub(
+        clock.target_tick(local_tick, config.lead_margin_ticks)
+    );
+    let drift = latest_tick.to_local_tick(0)
+    .map(|current| offset.wrapping_sub(current) as i32);
+    if drift.is_some_and(|d| d.unsigned_abs() <= config.resync_threshold(&dilation)) {
+        return;
+    }
+
+    latest_tick.set_tick_offset(offset);
+    info!(
+        "prediction mapped to server with offset: {offset} rtt: {:.3} drift: {drift:?}",
+        clock.rtt()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offset_follows_lowest_rtt_sample() {
+        let config = ClockConfig::default();
+        let mut clock = ServerClock::default();
+
+        clock.add_sample(0.1, 10.0, &config);
+        clock.add_sample(0.05, 12.0, &config);
+        clock.add_sample(0.3, 20.0, &config);
+        assert!(!clock.is_synced());
+        clock.add_sample(0.1, 11.0, &config);
+
+        assert!(clock.is_synced());
+        assert_eq!(clock.offset(), 12.0);
+        assert_eq!(clock.estimated_tick(100), 112);
+        assert!(clock.target_tick(100, 2) > 114);
+    }
+
+    #[test]
+    fn dilation_defers_resync() {
+        let config = ClockConfig::default();
+        let dilation_config = TickDilationConfig::default();
+        let idle = TickDilation::from_depth(dilation_config.target_depth, &dilation_config);
+        let dilated = TickDilation::from_depth(dilation_config.target_depth - 1, &dilation_config);
+
+        assert_eq!(config.resync_threshold(&idle), config.resync_threshold_ticks);
+        assert!(config.resync_threshold(&dilated) > config.resync_threshold_ticks);
+        // still re-mapped before server drops the inputs
+        assert!(config.resync_threshold(&dilated) <= INPUT_MAX_AHEAD_TICKS);
+    }
+}
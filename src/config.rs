@@ -1,4 +1,7 @@
-use bevy::utils::{SystemTime, Uuid};
+use bevy::{
+    math::Vec3,
+    utils::{SystemTime, Uuid}
+};
 
 pub const DEV_SERVER_TICK_RATE: f32 = 20.0;
 pub const DEV_SERVER_TICK_DELTA: f32 = 1.0 / DEV_SERVER_TICK_RATE;
@@ -15,6 +18,18 @@ pub const DEV_TOKEN_EXPIRE_SEC: u64 = 300;
 pub const DEV_MAX_UPDATE_SNAPSHOT_SIZE: usize = 2560;
 pub const DEV_MAX_SNAPSHOT_SIZE: usize = 64;
 
+// replicated translation is quantized to u16 per axis inside these bounds
+pub const NET_WORLD_BOUNDS_MIN: Vec3 = Vec3::new(-64.0, -32.0, -64.0);
+pub const NET_WORLD_BOUNDS_MAX: Vec3 = Vec3::new(64.0, 96.0, 64.0);
+// replicated velocities are quantized to i16 per axis in these steps
+pub const NET_VELOCITY_PRECISION: f32 = 0.01;
+pub const NET_ANGULAR_VELOCITY_PRECISION: f32 = 0.01;
+// replicated fields are resent only after moving further than these
+pub const DELTA_TRANSLATION_THRESHOLD: f32 = 0.005;
+pub const DELTA_ROTATION_THRESHOLD: f32 = 0.002;
+pub const DELTA_VELOCITY_THRESHOLD: f32 = 0.01;
+pub const DELTA_ANGULAR_VELOCITY_THRESHOLD: f32 = 0.01;
+
 pub const BASE_SPEED: f32 = 10.0;
 pub const BASE_ANGULAR_SPEED: f32 = 25.0; 
 
@@ -25,8 +40,150 @@ pub const TRANSLATION_ERROR_THRESHOLD: f32 = 1.0;
 pub const ROTATION_ERROR_THRESHOLD: f32 = 1.0;
 // 1sec / network tick
 pub const PREDICTION_ERROR_COUNT_THRESHOLD: u32 = 10;
+// drift smaller than this is ignored, larger drift is counted
+pub const TRANSLATION_ERROR_TOLERANCE: f32 = 0.01;
+pub const ROTATION_ERROR_TOLERANCE: f32 = 0.01;
+// 2sec of physics ticks
+pub const PREDICTION_HISTORY_LENGTH: usize = 128;
+// rendered correction error decays over this time
+pub const PREDICTION_SMOOTHING_SEC: f32 = 0.2;
+// balls spawned by prediction are despawned when server has not
+// replicated them within this long plus a round trip
+pub const PREDICTED_SPAWN_TIMEOUT_SEC: f64 = 1.0;
+
+// snapshots kept per interpolated body
+pub const INTERPOLATION_BUFFER_LENGTH: usize = 32;
+// render 2-3 network ticks behind the newest state
+pub const INTERPOLATION_DELAY_NETWORK_TICKS: f32 = 2.5;
+pub const INTERPOLATION_TIME_CORRECTION: f64 = 0.05;
+// bounds of the adaptive delay
+pub const INTERPOLATION_MIN_DELAY_NETWORK_TICKS: f32 = 1.5;
+pub const INTERPOLATION_MAX_DELAY_NETWORK_TICKS: f32 = 6.0;
+// delay added per measured jitter and per lost update ratio
+pub const INTERPOLATION_JITTER_SCALE: f32 = 3.0;
+pub const INTERPOLATION_LOSS_SCALE: f32 = 4.0;
+// grow fast to stop stutter, shrink slowly to stay stable
+pub const INTERPOLATION_DELAY_GROW_RATE: f32 = 0.5;
+pub const INTERPOLATION_DELAY_SHRINK_RATE: f32 = 0.02;
+// dead reckoning window after the newest state
+pub const EXTRAPOLATION_MAX_SEC: f32 = 0.25;
+// per fixed tick decay of the error left by extrapolation
+pub const EXTRAPOLATION_BLEND_RATE: f32 = 0.1;
+// predicted bodies return to server simulation after
+// their holder has not interacted with them for this long
+pub const PREDICTION_RELEASE_SEC: f32 = 2.0;
+// authority of a body stays with the new holder at least this long,
+// so that bodies in contact with several clients do not ping pong
+pub const AUTHORITY_HANDOFF_COOLDOWN_SEC: f32 = 0.5;
+// claims of authority older than this are denied, and claims further ahead
+// than clients predict are clamped, so that nobody wins by lying about the tick
+pub const AUTHORITY_CLAIM_MAX_AGE_SEC: f32 = LAG_COMPENSATION_MAX_REWIND_SEC;
+pub const AUTHORITY_CLAIM_MAX_LEAD_TICKS: u32 = PREDICTION_LEAD_MARGIN_TICKS 
++ PREDICTION_RESYNC_THRESHOLD_TICKS;
 
 pub const DISTANCE_CULLING_THREASHOLD: f32 = 100.0;
+// hidden again only beyond threshold + hysteresis, so that bodies
+// around the boundary do not flicker
+pub const DISTANCE_CULLING_HYSTERESIS: f32 = 10.0;
+
+// priority accumulated per network tick is halved at this distance
+pub const PRIORITY_DISTANCE_SCALE: f32 = 20.0;
+// extra priority per unit of speed
+pub const PRIORITY_VELOCITY_WEIGHT: f32 = 0.1;
+// multiplier for bodies owned by the client
+pub const PRIORITY_OWNER_WEIGHT: f32 = 2.0;
+// entity and component headers of replicon per updated body
+pub const PRIORITY_ENTITY_OVERHEAD: usize = 8;
+
+// states of client authoritative bodies beyond these are rejected
+pub const CLIENT_AUTHORITY_MAX_SPEED: f32 = 40.0;
+pub const CLIENT_AUTHORITY_MAX_ANGULAR_SPEED: f32 = 50.0;
+pub const CLIENT_AUTHORITY_TELEPORT_TOLERANCE: f32 = 0.5;
+// client ticks allowed beyond the server ticks elapsed between states,
+// covers jitter of the unreliable stream
+pub const CLIENT_AUTHORITY_TICK_SLACK: u32 = 16;
+// holder is moved back to the last accepted state at most this often,
+// states in flight meanwhile are rejected without another correction
+pub const CLIENT_AUTHORITY_CORRECTION_INTERVAL_SEC: f32 = 0.25;
+
+// every input message repeats this many of the newest frames,
+// so that a lost message is covered by the following ones
+pub const INPUT_REDUNDANCY: usize = 8;
+// frames further ahead of server than clients predict are dropped
+pub const INPUT_MAX_AHEAD_TICKS: u32 = PREDICTION_LEAD_MARGIN_TICKS + INPUT_REDUNDANCY as u32;
+// frames of a client server buffers at most
+pub const INPUT_BUFFER_MAX_LENGTH: usize = INPUT_MAX_AHEAD_TICKS as usize + INPUT_REDUNDANCY;
+
+pub const CLOCK_SYNC_INTERVAL_SEC: f64 = 0.25;
+// offset is taken from the sample with the lowest rtt in this window,
+// the one least affected by queuing delay
+pub const CLOCK_SYNC_SAMPLES: usize = 16;
+pub const CLOCK_SYNC_MIN_SAMPLES: usize = 4;
+pub const CLOCK_RTT_SMOOTHING: f64 = 0.125;
+// prediction runs ahead of server by rtt / 2 plus this many ticks
+pub const PREDICTION_LEAD_MARGIN_TICKS: u32 = 2;
+// prediction is re-mapped to server ticks only when drifted further than this
+pub const PREDICTION_RESYNC_THRESHOLD_TICKS: u32 = 3;
+// while tick dilation is moving prediction it is left to drift
+// up to the most server accepts inputs ahead
+pub const PREDICTION_DILATED_RESYNC_THRESHOLD_TICKS: u32 = INPUT_MAX_AHEAD_TICKS;
+
+// server reports how early inputs arrive every this many fixed ticks
+pub const INPUT_FEEDBACK_INTERVAL_TICKS: u32 = 16;
+// inputs should arrive this many ticks before they are applied,
+// same as the lead margin so that dilation and clock sync agree
+pub const INPUT_BUFFER_TARGET_TICKS: i32 = PREDICTION_LEAD_MARGIN_TICKS as i32;
+// fixed timestep of clients is scaled by at most this fraction
+pub const TICK_DILATION_MAX: f32 = 0.05;
+// dilation per tick of buffer error
+pub const TICK_DILATION_GAIN: f32 = 0.01;
+
+// holding fire for this long charges it fully
+pub const FIRE_CHARGE_SEC: f32 = 1.0;
+// balls spawn this far along the aim ray
+pub const FIRE_SPAWN_DISTANCE: f32 = 5.0;
+// launch speed at no charge and at full charge
+pub const FIRE_MIN_SPEED: f32 = 10.0;
+pub const FIRE_MAX_SPEED: f32 = 35.0;
+// aim rays starting further than this from the viewpoint of the client are rejected
+pub const FIRE_MAX_ORIGIN_DISTANCE: f32 = 1.0;
+
+// impulse of a force applied by the client, and the most server accepts
+pub const FORCE_MAGNITUDE: f32 = 100.0;
+pub const FORCE_MAX_MAGNITUDE: f32 = 150.0;
+// application points further than this from the center of the body are rejected,
+// ball radius with some slack for prediction error
+pub const FORCE_MAX_POINT_DISTANCE: f32 = 1.5;
+// forces of a client closer together than this are rejected
+pub const FORCE_COOLDOWN_SEC: f32 = 0.25;
+
+// client events accepted at once and per second after that, by event type
+pub const RATE_LIMIT_FIRE_BURST: f32 = 5.0;
+pub const RATE_LIMIT_FIRE_PER_SEC: f32 = 2.0;
+pub const RATE_LIMIT_FORCE_BURST: f32 = 2.0;
+pub const RATE_LIMIT_FORCE_PER_SEC: f32 = 1.0 / FORCE_COOLDOWN_SEC;
+pub const RATE_LIMIT_AUTHORITY_REQUEST_BURST: f32 = 10.0;
+pub const RATE_LIMIT_AUTHORITY_REQUEST_PER_SEC: f32 = 10.0;
+// streamed every fixed tick, with slack for dilation and bunched arrivals
+pub const RATE_LIMIT_INPUT_BURST: f32 = PHYSICS_FIXED_TICK_RATE * 0.5;
+pub const RATE_LIMIT_INPUT_PER_SEC: f32 = PHYSICS_FIXED_TICK_RATE * 1.25;
+// streamed every fixed tick per held body
+pub const RATE_LIMIT_AUTHORITY_STATE_BURST: f32 = RATE_LIMIT_AUTHORITY_STATE_PER_SEC * 0.5;
+pub const RATE_LIMIT_AUTHORITY_STATE_PER_SEC: f32 = PHYSICS_FIXED_TICK_RATE 
+* MAX_LIVE_FIRE_BALLS_PER_CLIENT as f32;
+pub const RATE_LIMIT_PING_BURST: f32 = 4.0;
+pub const RATE_LIMIT_PING_PER_SEC: f32 = 2.0 / CLOCK_SYNC_INTERVAL_SEC as f32;
+// fire of a client with this many balls alive is rejected
+pub const MAX_LIVE_FIRE_BALLS_PER_CLIENT: usize = 16;
+// violations within the window a client is kicked at, when kicking is the policy
+pub const ABUSE_KICK_VIOLATIONS: u32 = 20;
+pub const ABUSE_VIOLATION_WINDOW_SEC: f32 = 5.0;
+
+// entities of a disconnected client are kept this long for it to reconnect
+pub const DISCONNECT_GRACE_SEC: f32 = 10.0;
+
+// server casts against bodies as they were at most this long ago
+pub const LAG_COMPENSATION_MAX_REWIND_SEC: f32 = 0.5;
 
 pub const PHYSICS_FIXED_TICK_RATE: f32 = 64.0;
 pub const PHYSICS_FIXED_TICK_DELTA: f32 = 1.0 / PHYSICS_FIXED_TICK_RATE;
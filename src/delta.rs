@@ -0,0 +1,325 @@
+use bevy::{
+    prelude::*,
+    ecs::{component::Tick, system::SystemChangeTick}
+};
+use bevy_rapier3d::prelude::*;
+use bevy_replicon::prelude::*;
+use super::{
+    *,
+    config::*,
+    network_rigidbody::*
+};
+
+pub const DELTA_TRANSLATION: u8 = 1 << 0;
+pub const DELTA_ROTATION: u8 = 1 << 1;
+pub const DELTA_VELOCITY: u8 = 1 << 2;
+pub const DELTA_ANGULAR_VELOCITY: u8 = 1 << 3;
+pub const DELTA_ALL: u8 = DELTA_TRANSLATION
+| DELTA_ROTATION
+| DELTA_VELOCITY
+| DELTA_ANGULAR_VELOCITY;
+
+// replicated in place of NetworkRigidBody.
+// state only follows NetworkRigidBody on meaningful changes,
+// mask holds fields changed since the oldest state acknowledged by clients.
+// changes stay pending until published by priority
+#[derive(Component, Clone)]
+pub struct NetworkRigidBodyDelta {
+    state: NetworkRigidBody,
+    mask: u8,
+    at_rest: bool,
+    pending: bool,
+    field_ticks: [Tick; 4]
+}
+
+impl NetworkRigidBodyDelta {
+    #[inline]
+    pub fn new(state: NetworkRigidBody) -> Self {
+        Self{
+            state,
+            mask: DELTA_ALL,
+            at_rest: false,
+            pending: false,
+            field_ticks: [Tick::new(0); 4]
+        }
+    }
+
+    // fields out of mask are not meaningful
+    #[inline]
+    pub fn from_parts(state: NetworkRigidBody, mask: u8, at_rest: bool) -> Self {
+        Self{
+            state,
+            mask,
+            at_rest,
+            pending: false,
+            field_ticks: [Tick::new(0); 4]
+        }
+    }
+
+    #[inline]
+    pub fn state(&self) -> &NetworkRigidBody {
+        &self.state
+    }
+
+    #[inline]
+    pub fn mask(&self) -> u8 {
+        self.mask
+    }
+
+    // body is asleep on server, no further changes until woken up
+    #[inline]
+    pub fn is_at_rest(&self) -> bool {
+        self.at_rest
+    }
+
+    // has changes not yet visible to replication
+    #[inline]
+    pub fn is_pending(&self) -> bool {
+        self.pending
+    }
+
+    // called through Mut, which marks the component changed
+    // so that it is sent with the next update
+    #[inline]
+    pub fn publish(&mut self) {
+        self.pending = false;
+    }
+
+    // changed fields follow net_rb, stamped with the tick of the change
+    fn record(&mut self, net_rb: &NetworkRigidBody, changed: u8, at_rest: bool, tick: Tick) {
+        self.at_rest = at_rest;
+        self.pending = true;
+        if changed == DELTA_ALL {
+            self.state = net_rb.clone();
+        } else {
+            copy_fields(net_rb, &mut self.state, changed);
+        }
+        for (i, field_tick) in self.field_ticks.iter_mut().enumerate() {
+            if changed & (1 << i) != 0 {
+                *field_tick = tick;
+            }
+        }
+    }
+
+    // fields changed after the tick a client acked,
+    // every field for clients the body is not initialized on yet
+    fn unacked_fields(&self, acked: Option<Tick>, this_run: Tick) -> u8 {
+        let Some(acked) = acked else {
+            return DELTA_ALL;
+        };
+        let mut mask = 0;
+        for (i, tick) in self.field_ticks.iter().enumerate() {
+            if tick.is_newer_than(acked, this_run) {
+                mask |= 1 << i;
+            }
+        }
+        mask
+    }
+
+    // updates are unreliable, fields some client has not acked
+    // are published again until all of them have. this also repairs
+    // the last change before a body settles under the thresholds
+    fn set_unacked(&mut self, mask: u8) {
+        self.mask = mask;
+        if mask != 0 {
+            self.pending = true;
+        }
+    }
+
+    pub fn merge_into(&self, net_rb: &mut NetworkRigidBody) {
+        if self.mask == DELTA_ALL || !net_rb.is_same_mode(&self.state) {
+            *net_rb = self.state.clone();
+        } else {
+            copy_fields(&self.state, net_rb, self.mask);
+        }
+    }
+}
+
+pub struct DeltaCompressionPlugin;
+
+impl Plugin for DeltaCompressionPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(FixedUpdate,
+            update_network_rigidbody_delta_system
+            .after(AFTER_PHYSICS_SET)
+        )
+        .add_systems(PostUpdate,
+            update_network_rigidbody_delta_mask_system
+            .before(ServerSet::Send)
+        );
+    }
+}
+
+fn changed_fields(baseline: &NetworkRigidBody, current: &NetworkRigidBody) -> u8 {
+    let mut mask = 0;
+    if baseline.translation().distance(current.translation()) > DELTA_TRANSLATION_THRESHOLD {
+        mask |= DELTA_TRANSLATION;
+    }
+    if baseline.rotation().angle_between(current.rotation()) > DELTA_ROTATION_THRESHOLD {
+        mask |= DELTA_ROTATION;
+    }
+    if is_vec_changed(
+        baseline.velocity(),
+        current.velocity(),
+        DELTA_VELOCITY_THRESHOLD
+    ) {
+        mask |= DELTA_VELOCITY;
+    }
+    if is_vec_changed(
+        baseline.angular_velocity(),
+        current.angular_velocity(),
+        DELTA_ANGULAR_VELOCITY_THRESHOLD
+    ) {
+        mask |= DELTA_ANGULAR_VELOCITY;
+    }
+    mask
+}
+
+#[inline]
+fn is_vec_changed(baseline: Option<Vec3>, current: Option<Vec3>, threshold: f32) -> bool {
+    match (baseline, current) {
+        (Some(b), Some(c)) => b.distance(c) > threshold,
+        (None, None) => false,
+        _ => true
+    }
+}
+
+// both have to be the same mode
+fn copy_fields(from: &NetworkRigidBody, to: &mut NetworkRigidBody, mask: u8) {
+    match *to {
+        NetworkRigidBody::ServerSimulation {
+            ref mut tick,
+            ref mut translation,
+            ref mut rotation,
+            ref mut velocity,
+            ref mut angular_velocity
+        } => {
+            *tick = from.tick();
+            if mask & DELTA_TRANSLATION != 0 {
+                *translation = from.translation();
+            }
+            if mask & DELTA_ROTATION != 0 {
+                *rotation = from.rotation();
+            }
+            if mask & DELTA_VELOCITY != 0 {
+                *velocity = from.velocity();
+            }
+            if mask & DELTA_ANGULAR_VELOCITY != 0 {
+                *angular_velocity = from.angular_velocity();
+            }
+        }
+        NetworkRigidBody::ClientPrediction {
+            ref mut tick,
+            ref mut translation,
+            ref mut rotation,
+            ref mut velocity,
+            ref mut angular_velocity
+        } 
+        | NetworkRigidBody::ClientAuthority {
+            ref mut tick,
+            ref mut translation,
+            ref mut rotation,
+            ref mut velocity,
+            ref mut angular_velocity
+        } => {
+            *tick = from.tick();
+            if mask & DELTA_TRANSLATION != 0 {
+                *translation = from.translation();
+            }
+            if mask & DELTA_ROTATION != 0 {
+                *rotation = from.rotation();
+            }
+            if mask & DELTA_VELOCITY != 0 {
+                *velocity = from.velocity().unwrap_or_default();
+            }
+            if mask & DELTA_ANGULAR_VELOCITY != 0 {
+                *angular_velocity = from.angular_velocity().unwrap_or_default();
+            }
+        }
+    }
+}
+
+pub(crate) fn update_network_rigidbody_delta_system(
+    mut query: Query<(
+        &NetworkRigidBody, 
+        &mut NetworkRigidBodyDelta,
+        Option<&Sleeping>
+    )>,
+    change_tick: SystemChangeTick
+) {
+    for (net_rb, mut delta, sleeping) in query.iter_mut() {
+        let delta = delta.bypass_change_detection();
+        let at_rest = sleeping.is_some_and(|s| s.sleeping);
+        // rest pose is sent as is, not filtered by thresholds
+        let changed = if !net_rb.is_same_mode(&delta.state) 
+        || (at_rest && !delta.at_rest) {
+            DELTA_ALL
+        } else {
+            changed_fields(&delta.state, net_rb)
+        };
+        // barely moved, changes not acked yet are resent by the mask system
+        if changed == 0 && at_rest == delta.at_rest {
+            continue;
+        }
+
+        delta.record(net_rb, changed, at_rest, change_tick.this_run());
+    }
+}
+
+pub(crate) fn update_network_rigidbody_delta_mask_system(
+    mut query: Query<(Entity, &mut NetworkRigidBodyDelta)>,
+    mut connected_clients: ResMut<ConnectedClients>,
+    change_tick: SystemChangeTick
+) {
+    for (e, mut delta) in query.iter_mut() {
+        let mut mask = 0;
+        for client in connected_clients.iter_mut() {
+            if !client.visibility().is_visible(e) {
+                continue;
+            }
+            mask |= delta.unacked_fields(client.get_change_tick(e), change_tick.this_run());
+            if mask == DELTA_ALL {
+                break;
+            }
+        }
+
+        delta.bypass_change_detection().set_unacked(mask);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(x: f32) -> NetworkRigidBody {
+        NetworkRigidBody::ServerSimulation{
+            tick: 0,
+            translation: Vec3::X * x,
+            rotation: Quat::IDENTITY,
+            velocity: None,
+            angular_velocity: None
+        }
+    }
+
+    #[test]
+    fn lost_update_is_republished_until_acked() {
+        let mut delta = NetworkRigidBodyDelta::new(body(0.0));
+
+        // last change before the body settles under the thresholds
+        let changed = changed_fields(delta.state(), &body(1.0));
+        assert_eq!(changed, DELTA_TRANSLATION);
+        delta.record(&body(1.0), changed, false, Tick::new(10));
+        delta.publish();
+        assert!(!delta.is_pending());
+
+        // the update carrying it was lost, client still acks an older one
+        delta.set_unacked(delta.unacked_fields(Some(Tick::new(5)), Tick::new(20)));
+        assert!(delta.is_pending());
+        assert_eq!(delta.mask(), DELTA_TRANSLATION);
+        delta.publish();
+
+        delta.set_unacked(delta.unacked_fields(Some(Tick::new(20)), Tick::new(30)));
+        assert!(!delta.is_pending());
+        assert_eq!(delta.mask(), 0);
+    }
+}
@@ -0,0 +1,131 @@
+use bevy::prelude::*;
+use bevy_replicon::{prelude::*, client::ClientSet};
+use serde::{Serialize, Deserialize};
+use super::{
+    config::*,
+    network_rigidbody::*,
+    input::*
+};
+
+// lowest number of ticks inputs of the client arrived ahead of
+// the tick they were applied at, since the last feedback
+#[derive(Event, Serialize, Deserialize, Clone, Copy)]
+pub struct NetworkInputFeedback {
+    pub depth: i32
+}
+
+#[derive(Resource)]
+pub struct TickDilationConfig {
+    pub feedback_interval_ticks: u32,
+    pub target_depth: i32,
+    pub max_dilation: f32,
+    pub gain: f32
+}
+
+impl Default for TickDilationConfig {
+    fn default() -> Self {
+        Self{
+            feedback_interval_ticks: INPUT_FEEDBACK_INTERVAL_TICKS,
+            target_depth: INPUT_BUFFER_TARGET_TICKS,
+            max_dilation: TICK_DILATION_MAX,
+            gain: TICK_DILATION_GAIN
+        }
+    }
+}
+
+// client only, fraction the fixed tick rate is sped up by,
+// negative when slowed down. physics still steps by the same delta
+#[derive(Resource, Default)]
+pub struct TickDilation(f32);
+
+impl TickDilation {
+    #[inline]
+    pub fn get(&self) -> f32 {
+        self.0
+    }
+
+    // inputs arriving too early slow the client down, too late speed it up
+    #[inline]
+    pub fn from_depth(depth: i32, config: &TickDilationConfig) -> Self {
+        let error = (config.target_depth - depth) as f32;
+        Self((error * config.gain).clamp(-config.max_dilation, config.max_dilation))
+    }
+
+    #[inline]
+    pub fn timestep_seconds(&self) -> f64 {
+        PHYSICS_FIXED_TICK_DELTA as f64 / (1.0 + self.0 as f64)
+    }
+}
+
+pub struct TickDilationPlugin;
+
+impl Plugin for TickDilationPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TickDilationConfig>()
+        .init_resource::<TickDilation>()
+        .add_server_event::<NetworkInputFeedback>(ChannelKind::Unreliable)
+        .add_systems(FixedUpdate,
+            send_input_feedback_system
+            .after(apply_input_system)
+            .run_if(server_running)
+        )
+        .add_systems(PreUpdate,
+            apply_tick_dilation_system
+            .after(ClientSet::Receive)
+            .run_if(client_connected)
+        );
+    }
+}
+
+fn send_input_feedback_system(
+    mut buffers: ResMut<InputBuffers>,
+    mut feedbacks: EventWriter<ToClients<NetworkInputFeedback>>,
+    fixed_tick: Res<FixedTick>,
+    config: Res<TickDilationConfig>
+) {
+    if !fixed_tick.get().is_multiple_of(config.feedback_interval_ticks.max(1)) {
+        return;
+    }
+
+    for (client_id, buffer) in buffers.iter_mut() {
+        let Some(depth) = buffer.take_min_depth() else {
+            continue;
+        };
+
+        feedbacks.send(ToClients {
+            mode: SendMode::Direct(*client_id),
+            event: NetworkInputFeedback{ depth }
+        });
+    }
+}
+
+fn apply_tick_dilation_system(
+    mut feedbacks: EventReader<NetworkInputFeedback>,
+    mut dilation: ResMut<TickDilation>,
+    mut fixed_time: ResMut<Time<Fixed>>,
+    config: Res<TickDilationConfig>
+) {
+    let Some(feedback) = feedbacks.read().last() else {
+        return;
+    };
+
+    *dilation = TickDilation::from_depth(feedback.depth, &config);
+    fixed_time.set_timestep_seconds(dilation.timestep_seconds());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dilation_keeps_depth_around_target() {
+        let config = TickDilationConfig::default();
+
+        assert_eq!(TickDilation::from_depth(config.target_depth, &config).get(), 0.0);
+        assert!(TickDilation::from_depth(config.target_depth - 1, &config).get() > 0.0);
+        assert!(TickDilation::from_depth(config.target_depth + 1, &config).get() < 0.0);
+        // late inputs speed up by no more than the limit
+        assert_eq!(TickDilation::from_depth(-100, &config).get(), config.max_dilation);
+        assert!(TickDilation::from_depth(-100, &config).timestep_seconds() < PHYSICS_FIXED_TICK_DELTA as f64);
+    }
+}
@@ -0,0 +1,211 @@
+use bevy::{
+    prelude::*,
+    ecs::system::SystemParam,
+    utils::HashMap
+};
+use bevy_replicon::prelude::*;
+use super::{
+    *,
+    config::*,
+    network_rigidbody::*,
+    authority::*,
+    client_authority::*,
+    input::*,
+    rate_limit::*,
+    fire::*,
+    force::*
+};
+
+// what server does with the entities of a client when it disconnects.
+// balls the client held authority over are always handed back to server
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DisconnectPolicy {
+    // viewpoint and fire balls of the client are despawned
+    Despawn,
+    // kept for the grace period, despawned when the client has not
+    // reconnected with the same id by then. only useful with client ids
+    // stable across connections, which dev client ids are not
+    GracePeriod,
+    // fire balls are kept and simulated by server
+    TransferToServer
+}
+
+#[derive(Resource, Clone)]
+pub struct DisconnectConfig {
+    pub policy: DisconnectPolicy,
+    pub grace_ticks: u32
+}
+
+impl Default for DisconnectConfig {
+    fn default() -> Self {
+        Self{
+            policy: DisconnectPolicy::TransferToServer,
+            grace_ticks: (DISCONNECT_GRACE_SEC * PHYSICS_FIXED_TICK_RATE) as u32
+        }
+    }
+}
+
+// server only, fixed tick the entities of each disconnected client are kept until
+#[derive(Resource, Default)]
+pub struct DisconnectGrace(HashMap<ClientId, u32>);
+
+impl DisconnectGrace {
+    #[inline]
+    pub fn start(&mut self, client_id: ClientId, tick: u32, grace_ticks: u32) {
+        self.0.insert(client_id, tick.wrapping_add(grace_ticks));
+    }
+
+    // true when the client reconnected within its grace period
+    #[inline]
+    pub fn cancel(&mut self, client_id: ClientId) -> bool {
+        self.0.remove(&client_id).is_some()
+    }
+
+    pub fn take_expired(&mut self, tick: u32) -> Vec<ClientId> {
+        let mut expired = Vec::new();
+        self.0.retain(|client_id, until| {
+            let is_expired = tick.wrapping_sub(*until) as i32 > 0;
+            if is_expired {
+                expired.push(*client_id);
+            }
+            !is_expired
+        });
+        expired
+    }
+}
+
+// server only, state other modules keep per client
+#[derive(SystemParam)]
+pub struct ClientStates<'w> {
+    input_buffers: ResMut<'w, InputBuffers>,
+    limiter: ResMut<'w, RateLimiter>,
+    last_spawn_ids: ResMut<'w, LastSpawnIds>,
+    last_force_ticks: ResMut<'w, LastForceTicks>
+}
+
+impl ClientStates<'_> {
+    pub fn remove(&mut self, client_id: ClientId) {
+        self.input_buffers.remove(client_id);
+        self.limiter.remove(client_id);
+        self.last_spawn_ids.remove(client_id);
+        self.last_force_ticks.remove(client_id);
+    }
+}
+
+pub struct DisconnectPlugin;
+
+impl Plugin for DisconnectPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<DisconnectConfig>()
+        .init_resource::<DisconnectGrace>()
+        .add_systems(PreUpdate,
+            handle_disconnect_system
+            .after(ServerSet::Receive)
+        )
+        .add_systems(PostUpdate, remove_client_states_system);
+    }
+}
+
+#[allow(clippy::type_complexity)]
+pub(crate) fn handle_disconnect_system(
+    mut commands: Commands,
+    mut server_events: EventReader<ServerEvent>,
+    mut grace: ResMut<DisconnectGrace>,
+    viewpoints: Query<(Entity, &NetworkId)>,
+    mut balls: Query<(
+        Entity,
+        &NetworkFireBall,
+        &mut NetworkAuthority,
+        &mut NetworkRigidBody
+    )>,
+    fixed_tick: Res<FixedTick>,
+    config: Res<DisconnectConfig>
+) {
+    let tick = fixed_tick.get();
+    let mut cleanups = Vec::new();
+    for e in server_events.read() {
+        match e {
+            ServerEvent::ClientConnected { client_id } => {
+                if grace.cancel(*client_id) {
+                    info!("client: {client_id:?} reconnected within grace period");
+                }
+            }
+            ServerEvent::ClientDisconnected { client_id, .. } => {
+                if config.policy == DisconnectPolicy::GracePeriod {
+                    grace.start(*client_id, tick, config.grace_ticks);
+                }
+                cleanups.push((*client_id, config.policy));
+            }
+        }
+    }
+    for client_id in grace.take_expired(tick) {
+        info!("grace period of client: {client_id:?} expired");
+        cleanups.push((client_id, DisconnectPolicy::Despawn));
+    }
+
+    for (client_id, policy) in cleanups {
+        if policy != DisconnectPolicy::GracePeriod {
+            for (e, _) in viewpoints.iter()
+            .filter(|(_, net_id)| net_id.client_id() == client_id) {
+                commands.entity(e).despawn();
+            }
+        }
+
+        for (e, fire_ball, mut authority, mut net_rb) in balls.iter_mut() {
+            let is_cast = fire_ball.caster() == client_id;
+            if is_cast && policy == DisconnectPolicy::Despawn {
+                commands.entity(e).despawn();
+                continue;
+            }
+            // also balls kept for the grace period, so that they do not freeze
+            if !authority.is_held_by(client_id) {
+                continue;
+            }
+
+            *authority = NetworkAuthority::new(None);
+            let mode = net_rb.mode();
+            *net_rb = net_rb.with_mode(NetworkRigidBodyMode::ServerSimulation);
+            // body followed states of the holder, server simulates it from the last one
+            if mode == NetworkRigidBodyMode::ClientAuthority {
+                commands.entity(e)
+                .remove::<AuthorityValidation>()
+                .insert(generate_dynamic_ball(
+                    net_rb.velocity().unwrap_or_default(),
+                    net_rb.angular_velocity().unwrap_or_default()
+                ));
+            }
+        }
+
+        info!("entities of client: {client_id:?} cleaned up with policy: {policy:?}");
+    }
+}
+
+// after every system this frame handled events the client sent before leaving
+fn remove_client_states_system(
+    mut server_events: EventReader<ServerEvent>,
+    mut client_states: ClientStates
+) {
+    for e in server_events.read() {
+        if let ServerEvent::ClientDisconnected { client_id, .. } = e {
+            client_states.remove(*client_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grace_expires_unless_cancelled() {
+        let mut grace = DisconnectGrace::default();
+        let (first, second) = (ClientId::new(1), ClientId::new(2));
+        grace.start(first, 100, 10);
+        grace.start(second, 100, 10);
+
+        assert!(grace.take_expired(110).is_empty());
+        assert!(grace.cancel(second));
+        assert_eq!(grace.take_expired(111), vec![first]);
+        assert!(!grace.cancel(first));
+    }
+}
@@ -0,0 +1,156 @@
+use bevy::{prelude::*, utils::HashMap};
+use bevy_replicon::prelude::*;
+use super::{
+    *,
+    config::*,
+    network_rigidbody::*
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FireRejection {
+    NonFinite,
+    Direction,
+    Charge,
+    Origin,
+    // server has no viewpoint of the client to check the origin against
+    NoViewpoint,
+    OutOfBounds,
+    SpawnId,
+    RateLimited,
+    TooManyBalls
+}
+
+#[derive(Resource, Clone)]
+pub struct FireConfig {
+    // mode server spawns fire balls in
+    pub mode: NetworkRigidBodyMode,
+    pub spawn_distance: f32,
+    pub min_speed: f32,
+    pub max_speed: f32,
+    pub max_origin_distance: f32
+}
+
+impl Default for FireConfig {
+    fn default() -> Self {
+        Self{
+            mode: NetworkRigidBodyMode::ClientPrediction,
+            spawn_distance: FIRE_SPAWN_DISTANCE,
+            min_speed: FIRE_MIN_SPEED,
+            max_speed: FIRE_MAX_SPEED,
+            max_origin_distance: FIRE_MAX_ORIGIN_DISTANCE
+        }
+    }
+}
+
+// server only, newest spawn id accepted from each client.
+// clients match predicted balls by spawn id, which must not repeat
+#[derive(Resource, Default)]
+pub struct LastSpawnIds(HashMap<ClientId, u32>);
+
+impl LastSpawnIds {
+    #[inline]
+    pub fn is_reused(&self, client_id: ClientId, spawn_id: u32) -> bool {
+        self.0.get(&client_id)
+        .is_some_and(|l| spawn_id.wrapping_sub(*l) as i32 <= 0)
+    }
+
+    #[inline]
+    pub fn insert(&mut self, client_id: ClientId, spawn_id: u32) {
+        self.0.insert(client_id, spawn_id);
+    }
+
+    // spawn ids start over when a client reconnects
+    #[inline]
+    pub fn remove(&mut self, client_id: ClientId) {
+        self.0.remove(&client_id);
+    }
+}
+
+impl FireConfig {
+    // spawn position and velocity of the ball, computed the same way
+    // by server and by the client predicting it
+    pub fn spawn(&self, fire: &NetworkFire, viewpoint: Vec3)
+    -> Result<(Vec3, Vec3), FireRejection> {
+        if !fire.origin.is_finite()
+        || !fire.direction.is_finite()
+        || !fire.charge.is_finite() {
+            return Err(FireRejection::NonFinite);
+        }
+
+        let Some(direction) = fire.direction.try_normalize() else {
+            return Err(FireRejection::Direction);
+        };
+        if !(0.0..=1.0).contains(&fire.charge) {
+            return Err(FireRejection::Charge);
+        }
+        if fire.origin.distance(viewpoint) > self.max_origin_distance {
+            return Err(FireRejection::Origin);
+        }
+
+        let position = fire.origin + direction * self.spawn_distance;
+        if position.cmplt(NET_WORLD_BOUNDS_MIN).any()
+        || position.cmpgt(NET_WORLD_BOUNDS_MAX).any() {
+            return Err(FireRejection::OutOfBounds);
+        }
+
+        let speed = self.min_speed + (self.max_speed - self.min_speed) * fire.charge;
+        Ok((position, direction * speed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fire(origin: Vec3, direction: Vec3, charge: f32) -> NetworkFire {
+        NetworkFire{
+            spawn_id: 1,
+            origin,
+            direction,
+            charge
+        }
+    }
+
+    #[test]
+    fn spawns_along_aim_ray_with_charged_speed() {
+        let config = FireConfig::default();
+        let viewpoint = Vec3::new(0.0, 70.0, 25.0);
+
+        let (position, velocity) = config.spawn(
+            &fire(viewpoint, Vec3::NEG_Y * 2.0, 1.0),
+            viewpoint
+        ).unwrap();
+        assert_eq!(position, viewpoint + Vec3::NEG_Y * config.spawn_distance);
+        assert_eq!(velocity, Vec3::NEG_Y * config.max_speed);
+
+        let (_, velocity) = config.spawn(&fire(viewpoint, Vec3::NEG_Y, 0.0), viewpoint).unwrap();
+        assert_eq!(velocity.length(), config.min_speed);
+    }
+
+    #[test]
+    fn rejects_values_out_of_limits() {
+        let config = FireConfig::default();
+        let viewpoint = Vec3::new(0.0, 70.0, 25.0);
+
+        assert_eq!(
+            config.spawn(&fire(viewpoint, Vec3::ZERO, 0.5), viewpoint),
+            Err(FireRejection::Direction)
+        );
+        assert_eq!(
+            config.spawn(&fire(viewpoint, Vec3::NEG_Y, 1.5), viewpoint),
+            Err(FireRejection::Charge)
+        );
+        assert_eq!(
+            config.spawn(&fire(Vec3::ZERO, Vec3::NEG_Y, 0.5), viewpoint),
+            Err(FireRejection::Origin)
+        );
+        assert_eq!(
+            config.spawn(&fire(Vec3::Y * 95.0, Vec3::Y, 0.5), Vec3::Y * 95.0),
+            Err(FireRejection::OutOfBounds)
+        );
+        assert_eq!(
+            config.spawn(&fire(Vec3::NAN, Vec3::Y, 0.5), viewpoint),
+            Err(FireRejection::NonFinite)
+        );
+    }
+}
@@ -0,0 +1,146 @@
+use bevy::{prelude::*, utils::HashMap};
+use bevy_rapier3d::prelude::*;
+use bevy_replicon::prelude::*;
+use super::{
+    *,
+    config::*
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForceRejection {
+    NonFinite,
+    Direction,
+    Magnitude,
+    Point,
+    Cooldown,
+    NotHolder
+}
+
+#[derive(Resource, Clone)]
+pub struct ForceConfig {
+    pub magnitude: f32,
+    pub max_magnitude: f32,
+    pub max_point_distance: f32,
+    pub cooldown_ticks: u32
+}
+
+impl Default for ForceConfig {
+    fn default() -> Self {
+        Self{
+            magnitude: FORCE_MAGNITUDE,
+            max_magnitude: FORCE_MAX_MAGNITUDE,
+            max_point_distance: FORCE_MAX_POINT_DISTANCE,
+            cooldown_ticks: (FORCE_COOLDOWN_SEC * PHYSICS_FIXED_TICK_RATE) as u32
+        }
+    }
+}
+
+// server only, fixed tick the last force of each client was applied at
+#[derive(Resource, Default)]
+pub struct LastForceTicks(HashMap<ClientId, u32>);
+
+impl LastForceTicks {
+    #[inline]
+    pub fn get(&self, client_id: ClientId) -> Option<u32> {
+        self.0.get(&client_id).copied()
+    }
+
+    #[inline]
+    pub fn insert(&mut self, client_id: ClientId, tick: u32) {
+        self.0.insert(client_id, tick);
+    }
+
+    #[inline]
+    pub fn remove(&mut self, client_id: ClientId) {
+        self.0.remove(&client_id);
+    }
+}
+
+impl ForceConfig {
+    // impulse applied to the body centered at center, computed the same way
+    // by server and by the client predicting it
+    pub fn impulse(&self, force: &NetworkForce, center: Vec3)
+    -> Result<ExternalImpulse, ForceRejection> {
+        if !force.direction.is_finite()
+        || !force.magnitude.is_finite()
+        || !force.point.is_finite() {
+            return Err(ForceRejection::NonFinite);
+        }
+
+        let Some(direction) = force.direction.try_normalize() else {
+            return Err(ForceRejection::Direction);
+        };
+        if !(0.0..=self.max_magnitude).contains(&force.magnitude) {
+            return Err(ForceRejection::Magnitude);
+        }
+        if force.point.distance(center) > self.max_point_distance {
+            return Err(ForceRejection::Point);
+        }
+
+        Ok(ExternalImpulse::at_point(
+            direction * force.magnitude,
+            force.point,
+            center
+        ))
+    }
+
+    // fixed tick the last force of the client was applied at
+    #[inline]
+    pub fn is_cooling_down(&self, last_tick: Option<u32>, tick: u32) -> bool {
+        last_tick.is_some_and(|l| tick.wrapping_sub(l) < self.cooldown_ticks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn force(direction: Vec3, magnitude: f32, point: Vec3) -> NetworkForce {
+        NetworkForce{
+            entity: Entity::PLACEHOLDER,
+            direction,
+            magnitude,
+            point
+        }
+    }
+
+    #[test]
+    fn off_center_point_adds_torque() {
+        let config = ForceConfig::default();
+        let center = Vec3::new(0.0, 15.0, 0.0);
+
+        let impulse = config.impulse(&force(Vec3::X * 2.0, 100.0, center), center).unwrap();
+        assert_eq!(impulse.impulse, Vec3::X * 100.0);
+        assert_eq!(impulse.torque_impulse, Vec3::ZERO);
+
+        let impulse = config.impulse(&force(Vec3::X, 100.0, center + Vec3::Y), center).unwrap();
+        assert_eq!(impulse.torque_impulse, Vec3::NEG_Z * 100.0);
+    }
+
+    #[test]
+    fn rejects_values_out_of_limits() {
+        let config = ForceConfig::default();
+        let center = Vec3::ZERO;
+
+        assert_eq!(
+            config.impulse(&force(Vec3::ZERO, 100.0, center), center),
+            Err(ForceRejection::Direction)
+        );
+        assert_eq!(
+            config.impulse(&force(Vec3::X, config.max_magnitude + 1.0, center), center),
+            Err(ForceRejection::Magnitude)
+        );
+        assert_eq!(
+            config.impulse(&force(Vec3::X, 100.0, Vec3::Y * 10.0), center),
+            Err(ForceRejection::Point)
+        );
+        assert_eq!(
+            config.impulse(&force(Vec3::X, f32::NAN, center), center),
+            Err(ForceRejection::NonFinite)
+        );
+
+        assert!(!config.is_cooling_down(None, 100));
+        assert!(config.is_cooling_down(Some(100), 101));
+        assert!(!config.is_cooling_down(Some(100), 100 + config.cooldown_ticks));
+    }
+}
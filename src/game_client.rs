@@ -1,11 +1,25 @@
-use bevy::prelude::*;
+use bevy::{
+    prelude::*, 
+    ecs::system::EntityCommands, 
+    time::Real,
+    window::PrimaryWindow
+};
 use bevy_rapier3d::prelude::*;
 use bevy_replicon::client::ClientSet;
 use client_builder::Client;
 use super::{
     *,
     network_rigidbody::*,
-    level::*
+    prediction::*,
+    interpolation::*,
+    client_authority::*,
+    authority::*,
+    input::*,
+    clock::*,
+    fire::*,
+    force::*,
+    level::*,
+    config::*
 };
 
 pub struct GameClientPlugin;
@@ -14,6 +28,8 @@ impl Plugin for GameClientPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins((
             GameCommonPlugin,
+            PredictionPlugin,
+            InterpolationPlugin,
             RapierDebugRenderPlugin::default()
         ))
         .add_systems(Startup, (
@@ -23,179 +39,511 @@ impl Plugin for GameClientPlugin {
         ))
         .add_systems(PreUpdate, (
             handle_fire,
-            handle_force
+            handle_mode_switch,
+            despawn_unadopted_fire.after(handle_fire)
         ).after(ClientSet::Receive))
         .add_systems(FixedUpdate, (
-            apply_net_rb_velocity_system,
-            update_net_rb_cache_system,
-            apply_net_rb_interpolation_system
-        ).chain(
+            predict_fire,
+            handle_force
+        ).after(send_input_system
         ).before(BEFORE_PHYSICS_SET))
         .add_systems(FixedUpdate, 
             draw_net_rb_gizmos_system
             .after(AFTER_PHYSICS_SET)
         )
-        .add_systems(Update, handle_input);
+        .add_systems(Update, (
+            handle_fire_input,
+            handle_force_input
+        ));
+    }
+}
+
+// ray from the camera through the cursor, 
+// camera forward when the cursor is outside the window
+fn aim_ray(
+    windows: &Query<&Window, With<PrimaryWindow>>,
+    cameras: &Query<(&Camera, &GlobalTransform)>
+) -> Option<(Vec3, Vec3)> {
+    let (camera, camera_transform) = cameras.get_single().ok()?;
+    let cursor = windows.get_single()
+    .ok()
+    .and_then(|w| w.cursor_position());
+
+    match cursor.and_then(|c| camera.viewport_to_world(camera_transform, c)) {
+        Some(ray) => Some((ray.origin, *ray.direction)),
+        None => Some((camera_transform.translation(), camera_transform.forward()))
     }
 }
 
-fn handle_input(
+// sent with the input frame of the next fixed tick.
+// fire charges while held and aims through the cursor when released
+fn handle_fire_input(
     keyboard: Res<ButtonInput<KeyCode>>,
-    mut fire: EventWriter<NetworkFire>,
-    mut force: EventWriter<NetworkForce>
+    mut pending: ResMut<PendingInput>,
+    mut charge_started: Local<Option<f32>>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    time: Res<Time>
 ) {
     if keyboard.just_pressed(FIRE_KEY) {
-        fire.send(NetworkFire);
+        *charge_started = Some(time.elapsed_seconds());
+    }
+
+    if keyboard.just_released(FIRE_KEY) {
+        let charge = charge_started.take()
+        .map_or(0.0, |t| (time.elapsed_seconds() - t) / FIRE_CHARGE_SEC)
+        .clamp(0.0, 1.0);
+
+        if let Some((origin, direction)) = aim_ray(&windows, &cameras) {
+            pending.fire(origin, direction, charge);
+        }
+    }
+}
+
+// pushes the held ball under the cursor away from the camera,
+// at the point the aim ray hits it
+#[allow(clippy::too_many_arguments)]
+fn handle_force_input(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut pending: ResMut<PendingInput>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    balls: Query<&NetworkAuthority>,
+    rapier_context: Res<RapierContext>,
+    client: Res<Client>,
+    config: Res<ForceConfig>
+) {
+    if !keyboard.just_pressed(FORCE_KEY) {
+        return;
     }
+    let Some((origin, direction)) = aim_ray(&windows, &cameras) else {
+        return;
+    };
 
-    if keyboard.just_pressed(FORCE_KEY) {
-        force.send(NetworkForce);
+    let is_held = |e| balls.get(e).is_ok_and(|a| a.is_held_by_local(&client));
+    let filter = QueryFilter::new().predicate(&is_held);
+    if let Some((entity, toi)) = rapier_context.cast_ray(
+        origin, 
+        direction, 
+        f32::MAX, 
+        true, 
+        filter
+    ) {
+        pending.force(NetworkForce{
+            entity,
+            direction,
+            magnitude: config.magnitude,
+            point: origin + direction * toi
+        });
     }
 }
 
+// validated like server does, so that rejected forces are not predicted
+#[allow(clippy::type_complexity)]
 fn handle_force(
     mut commands: Commands,
-    query: Query<(Entity, &NetworkFireBall)>,
+    mut query: Query<(
+        &Transform,
+        &NetworkAuthority,
+        Option<&mut PredictionHistory>,
+        Option<&mut Sleeping>
+    )>,
     mut force: EventReader<NetworkForce>,
-    client: Res<Client>
+    mut last_force_tick: Local<Option<u32>>,
+    client: Res<Client>,
+    fixed_tick: Res<FixedTick>,
+    config: Res<ForceConfig>
 ) {
-    for _ in force.read() {
-        for (e, ball) in query.iter() {
-            if ball.caster()
-            .get() == client.id() {
-                commands.entity(e)
-                .insert(ExternalImpulse{
-                    impulse: EXTRA_FORCE,
-                    torque_impulse: EXTRA_TORQUE
-                });
-            }
+    for event in force.read() {
+        let Ok((transform, authority, history, sleeping)) = query.get_mut(event.entity) else {
+            continue;
+        };
+        if !authority.is_held_by_local(&client)
+        || config.is_cooling_down(*last_force_tick, fixed_tick.get()) {
+            continue;
+        }
+        let Ok(impulse) = config.impulse(event, transform.translation) else {
+            continue;
+        };
+        *last_force_tick = Some(fixed_tick.get());
+
+        commands.entity(event.entity).insert(impulse);
+
+        if let Some(mut sleeping) = sleeping {
+            sleeping.sleeping = false;
+        }
+
+        // kept for re-simulation, applied by the physics step of this tick
+        if let Some(mut history) = history {
+            history.push_input(PredictedImpulse{
+                tick: fixed_tick.get(),
+                impulse: impulse.impulse,
+                torque_impulse: impulse.torque_impulse
+            });
         }
     }
 }
 
+// client rapier setup currently applied to a ball
+#[derive(Component, Clone, Copy, PartialEq, Eq)]
+enum BodySetup {
+    Interpolated,
+    Predicted,
+    LocalAuthority
+}
+
+impl BodySetup {
+    // only the holder predicts or simulates, everyone else interpolates
+    fn of(net_rb: &NetworkRigidBody, authority: &NetworkAuthority, client: &Client) -> Self {
+        match net_rb {
+            &NetworkRigidBody::ClientPrediction { .. } 
+            if authority.is_held_by_local(client) => BodySetup::Predicted,
+            &NetworkRigidBody::ClientAuthority { .. } 
+            if is_local_authority(net_rb, authority, client) => BodySetup::LocalAuthority,
+            &NetworkRigidBody::ServerSimulation { .. } 
+            | &NetworkRigidBody::ClientPrediction { .. } 
+            | &NetworkRigidBody::ClientAuthority { .. } => BodySetup::Interpolated
+        }
+    }
+}
+
+// rendered pose is kept where it was, the offset 
+// from the new setup decays the same way corrections do
+fn insert_body_setup(
+    entity: &mut EntityCommands,
+    setup: BodySetup,
+    net_rb: &NetworkRigidBody,
+    rendered: Transform,
+    render_tick: Option<f64>,
+    interpolation_config: &InterpolationConfig
+) {
+    let velocity = net_rb.velocity().unwrap_or_default();
+    let angular_velocity = net_rb.angular_velocity().unwrap_or_default();
+
+    match setup {
+        BodySetup::Interpolated => {
+            let mut buffer = SnapshotBuffer::with_capacity(
+                interpolation_config.buffer_length
+            );
+            buffer.insert(Snapshot::from_net_rb(net_rb));
+            let (translation, rotation) = render_tick
+            .and_then(|t| buffer.sample(t, InterpolationMode::Hermite))
+            .unwrap_or((net_rb.translation(), net_rb.rotation()));
+
+            entity.insert((
+                buffer,
+                ExtrapolationBlend::from_offset(
+                    rendered.translation - translation,
+                    (rendered.rotation * rotation.inverse()).normalize()
+                ),
+                InterpolationMode::Hermite,
+                generate_kinematic_ball()
+            ));
+        }
+        BodySetup::Predicted => {
+            let translation = net_rb.translation();
+            let rotation = net_rb.rotation();
+
+            entity.insert((
+                Transform{
+                    translation,
+                    rotation,
+                    ..default()
+                },
+                PredictionHistory::default(),
+                PredictionSmoothing::from_offset(
+                    rendered.translation - translation,
+                    (rendered.rotation * rotation.inverse()).normalize()
+                ),
+                generate_dynamic_ball(velocity, angular_velocity)
+            ));
+        }
+        BodySetup::LocalAuthority => {
+            entity.insert((
+                rendered,
+                LocalAuthority,
+                generate_dynamic_ball(velocity, angular_velocity)
+            ));
+        }
+    }
+}
+
+// client only, ball spawned by prediction, waiting for 
+// the replicated ball with the same spawn id to adopt it
+#[derive(Component)]
+struct PredictedSpawn {
+    spawn_id: u32,
+    spawned_at: f64
+}
+
+fn spawn_ball_visual(
+    entity: &mut EntityCommands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>
+) {
+    let mesh = meshes.add(Mesh::from(Sphere::new(BALL_RADIUS)));
+    let material = materials.add(BALL_COLOR);
+
+    // rendered by a child so that corrections of the body
+    // and switches between setups can be smoothed out visually
+    entity.with_children(|parent| {
+        parent.spawn((
+            PbrBundle{
+                mesh,
+                material,
+                ..default()
+            },
+            PredictionVisual
+        ));
+    });
+}
+
+// spawned on the same tick server spawns the ball at,
+// fire server would reject is not predicted
+fn predict_fire(
+    mut commands: Commands,
+    mut fire: EventReader<NetworkFire>,
+    cameras: Query<&GlobalTransform, With<Camera>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    time: Res<Time<Real>>,
+    config: Res<FireConfig>
+) {
+    let Ok(camera_transform) = cameras.get_single() else {
+        return;
+    };
+
+    for event in fire.read() {
+        let Ok((translation, velocity)) = config.spawn(event, camera_transform.translation()) else {
+            continue;
+        };
+
+        let mut entity = commands.spawn((
+            SpatialBundle::from_transform(Transform{
+                translation,
+                rotation: BALL_SPAWN_ROTATION,
+                ..default()
+            }),
+            PredictionHistory::default(),
+            PredictionSmoothing::default(),
+            PredictedSpawn{
+                spawn_id: event.spawn_id,
+                spawned_at: time.elapsed_seconds_f64()
+            },
+            generate_dynamic_ball(velocity, INITIAL_ANGULAR_VELOCITY)
+        ));
+        spawn_ball_visual(&mut entity, &mut meshes, &mut materials);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+#[allow(clippy::type_complexity)]
 fn handle_fire(
     mut commands: Commands,
     query: Query<(
         Entity, 
         &NetworkRigidBody,
         &NetworkFireBall,
+        &NetworkAuthority
     ), 
         Added<NetworkFireBall>
     >,
+    mut predicted: Query<(
+        Entity,
+        &Transform,
+        &Velocity,
+        &mut PredictionHistory,
+        &PredictionSmoothing,
+        &PredictedSpawn
+    )>,
     mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>
-
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    interpolation_config: Res<InterpolationConfig>,
+    interpolation_time: Res<InterpolationTime>,
+    client: Res<Client>
 ) {
-    for (e, net_rb, net_ball) in query.iter() {
-        let (trans, euler) = match net_rb {
-            &NetworkRigidBody::ServerSimulation { translation, euler } 
-            => (translation, euler),
-            &NetworkRigidBody::ClientPrediction { translation, euler, .. } 
-            => (translation, euler)
+    for (e, net_rb, net_ball, authority) in query.iter() {
+        let setup = BodySetup::of(net_rb, authority, &client);
+        let adopted = if net_ball.caster().get() == client.id() {
+            predicted.iter_mut()
+            .find(|(.., spawn)| spawn.spawn_id == net_ball.spawn_id())
+        } else {
+            None
         };
 
-        commands.entity(e)
-        .insert(
-            PbrBundle{
-                mesh: meshes.add(Mesh::from(Sphere::new(BALL_RADIUS))),
-                material: materials.add(BALL_COLOR),
-                transform: Transform{
-                    translation: trans,
-                    rotation: euler_to_quat(euler),
+        let mut entity = commands.entity(e);
+        match adopted {
+            // keeps simulating from the predicted present, 
+            // server states reconcile it through the same history
+            Some((
+                predicted_entity, 
+                transform, 
+                velocity, 
+                mut history, 
+                smoothing, 
+                _
+            )) if setup == BodySetup::Predicted => {
+                entity.insert((
+                    SpatialBundle::from_transform(*transform),
+                    setup,
+                    std::mem::take(&mut *history),
+                    PredictionSmoothing::from_offset(
+                        smoothing.translation(), 
+                        smoothing.rotation()
+                    ),
+                    generate_dynamic_ball(velocity.linvel, velocity.angvel)
+                ));
+                commands.entity(predicted_entity)
+                .despawn_recursive();
+            }
+            // rendered pose carries over to the setup server chose
+            Some((predicted_entity, transform, .., smoothing, _)) => {
+                let rendered = Transform{
+                    translation: transform.translation + smoothing.translation(),
+                    rotation: smoothing.rotation() * transform.rotation,
                     ..default()
-                },
-                ..default()
+                };
+                entity.insert((
+                    SpatialBundle::from_transform(rendered),
+                    setup
+                ));
+                insert_body_setup(
+                    &mut entity, 
+                    setup, 
+                    net_rb, 
+                    rendered, 
+                    interpolation_time.render_tick(), 
+                    &interpolation_config
+                );
+                commands.entity(predicted_entity)
+                .despawn_recursive();
             }
-        );
-
-        match net_rb {
-            &NetworkRigidBody::ServerSimulation { .. } => {
-                commands.entity(e)
-                .insert((
-                    Cache::<NetworkRigidBody> {
-                        latest: net_rb.clone(),
-                        second: net_rb.clone(),
-                        elapsed_time: -1.0
-                    },
-                    generate_kinematic_ball()
+            None => {
+                let transform = Transform{
+                    translation: net_rb.translation(),
+                    rotation: net_rb.rotation(),
+                    ..default()
+                };
+                entity.insert((
+                    SpatialBundle::from_transform(transform),
+                    setup
                 ));
-            },
-            &NetworkRigidBody::ClientPrediction { velocity, angular_velocity, .. } => {
-                commands.entity(e)
-                .insert(generate_dynamic_ball(velocity, angular_velocity));
+                insert_body_setup(
+                    &mut entity, 
+                    setup, 
+                    net_rb, 
+                    transform, 
+                    interpolation_time.render_tick(), 
+                    &interpolation_config
+                );
             }
         }
+        spawn_ball_visual(&mut commands.entity(e), &mut meshes, &mut materials);
 
         info!(
-            "fire ball: {e:?} spawned by : {:?}", 
-            net_ball.caster()
+            "fire ball: {e:?} spawned by : {:?} spawn id: {}", 
+            net_ball.caster(),
+            net_ball.spawn_id()
         );
     }
 }
 
-fn apply_net_rb_velocity_system(
-    mut query: Query<
-        (&NetworkRigidBody, &mut Velocity),
-        Changed<NetworkRigidBody>
-    >
+// predicted balls server rejected or never replicated
+fn despawn_unadopted_fire(
+    mut commands: Commands,
+    query: Query<(Entity, &PredictedSpawn)>,
+    fire_balls: Query<&NetworkFireBall, Added<NetworkFireBall>>,
+    mut rejected: EventReader<NetworkFireRejected>,
+    clock: Res<ServerClock>,
+    time: Res<Time<Real>>,
+    client: Res<Client>
 ) {
-    for (net_rb, mut velocity) in query.iter_mut() {
-        let (linear, angular) = match net_rb {
-            &NetworkRigidBody::ClientPrediction { velocity, angular_velocity, .. }
-            => (velocity, angular_velocity),
-            _ => panic!("should be client predicted RB")
-        };
+    let rejected = rejected.read()
+    .map(|r| r.spawn_id)
+    .collect::<Vec<_>>();
+    // handle_fire already despawns these, its commands may not be applied yet
+    let adopted = fire_balls.iter()
+    .filter(|b| b.caster().get() == client.id())
+    .map(|b| b.spawn_id())
+    .collect::<Vec<_>>();
+    let timeout = PREDICTED_SPAWN_TIMEOUT_SEC + clock.rtt();
 
-        velocity.linvel = linear;
-        velocity.angvel = angular;
-    }
-}
+    for (e, spawn) in query.iter() {
+        if adopted.contains(&spawn.spawn_id) {
+            continue;
+        }
 
-fn update_net_rb_cache_system(
-    mut query: Query<
-        (&NetworkRigidBody, &mut Cache<NetworkRigidBody>),
-        Changed<NetworkRigidBody>
-    >
-) {
-    for (net_rb, mut cache) in query.iter_mut() {
-        cache.second = if cache.elapsed_time < 0.0 {
-            net_rb.clone()
-        } else {
-            cache.latest.clone()
-        };
-        cache.latest = net_rb.clone();
-        cache.elapsed_time = 0.0;
+        if rejected.contains(&spawn.spawn_id) 
+        || time.elapsed_seconds_f64() - spawn.spawned_at > timeout {
+            info!("predicted fire ball: {e:?} spawn id: {} was not adopted", spawn.spawn_id);
+            commands.entity(e)
+            .despawn_recursive();
+        }
     }
 }
 
-fn apply_net_rb_interpolation_system(
+// swaps rapier setup when server changes the mode 
+// or the authority holder of a ball
+#[allow(clippy::type_complexity)]
+fn handle_mode_switch(
+    mut commands: Commands,
     mut query: Query<(
-        &mut Cache<NetworkRigidBody>, 
-        &mut Transform
-    )>,
-    fixed_time: Res<Time<Fixed>>
+        Entity,
+        &NetworkRigidBody,
+        &NetworkAuthority,
+        &Transform,
+        &Children,
+        &mut BodySetup
+    ), 
+        Or<(Changed<NetworkRigidBody>, Changed<NetworkAuthority>)>
+    >,
+    visuals: Query<&Transform, With<PredictionVisual>>,
+    interpolation_config: Res<InterpolationConfig>,
+    interpolation_time: Res<InterpolationTime>,
+    client: Res<Client>
 ) {
-    for (mut cache, mut transform) in query.iter_mut() {
-        let (latest_trans, latest_rot) = match cache.latest {
-            NetworkRigidBody::ServerSimulation { translation, euler } 
-            => (translation, euler_to_quat(euler)),
-            _ => panic!("should be server simulated RB") 
-        };
-        let (second_trans, second_rot) = match cache.second {
-            NetworkRigidBody::ServerSimulation { translation, euler } 
-            => (translation, euler_to_quat(euler)),
-            _ => panic!("should be server simulated RB")
-        };
+    for (e, net_rb, authority, transform, children, mut current) in query.iter_mut() {
+        let setup = BodySetup::of(net_rb, authority, &client);
+        if setup == *current {
+            continue;
+        }
 
-        let per = (cache.elapsed_time / DEV_NETWORK_TICK_DELTA)
-        .clamp(0.0, 1.0);
-        let translation = second_trans.lerp(latest_trans, per);
-        let rotation = second_rot.slerp(latest_rot, per);
-    
-        transform.translation = translation;
-        transform.rotation = rotation;
+        let mut rendered = *transform;
+        for &child in children.iter() {
+            if let Ok(visual) = visuals.get(child) {
+                rendered = transform.mul_transform(*visual);
+                commands.entity(child)
+                .insert(Transform::IDENTITY);
+            }
+        }
 
-        cache.elapsed_time += fixed_time.delta_seconds();
+        let mut entity = commands.entity(e);
+        entity.remove::<(
+            SnapshotBuffer,
+            ExtrapolationBlend,
+            InterpolationMode,
+            PredictionHistory,
+            PredictionSmoothing,
+            LocalAuthority,
+            Velocity,
+            Restitution,
+            Sleeping
+        )>();
+        insert_body_setup(
+            &mut entity, 
+            setup, 
+            net_rb, 
+            rendered, 
+            interpolation_time.render_tick(), 
+            &interpolation_config
+        );
+
+        info!(
+            "fire ball: {e:?} switched to mode: {:?} held by: {:?}", 
+            net_rb.mode(),
+            authority.holder()
+        );
+        *current = setup;
     }
 }
 
@@ -204,16 +552,9 @@ fn draw_net_rb_gizmos_system(
     mut gizmos: Gizmos
 ) {
     for net_rb in query.iter() {
-        let (trans, rot) = match net_rb {
-            &NetworkRigidBody::ServerSimulation { translation, euler } 
-            => (translation, euler_to_quat(euler)), 
-            &NetworkRigidBody::ClientPrediction { translation, euler, .. } 
-            => (translation, euler_to_quat(euler))
-        };
-
         gizmos.sphere(
-            trans, 
-            rot, 
+            net_rb.translation(), 
+            net_rb.rotation(), 
             BALL_RADIUS, 
             Color::GREEN
         );
@@ -1,47 +1,82 @@
-use bevy::prelude::*;
+use bevy::{prelude::*, utils::HashMap};
 use bevy_replicon::prelude::*;
 use super::{
     *, 
     level::*,
-    network_rigidbody::*
+    network_rigidbody::*,
+    delta::*,
+    interest::*,
+    priority::*,
+    client_authority::*,
+    authority::*,
+    input::*,
+    lag_compensation::*,
+    fire::*,
+    force::*,
+    rate_limit::*,
+    disconnect::*
 };
 
 pub struct GameServerPlugin;
 
 impl Plugin for GameServerPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins(GameCommonPlugin)
+        app.add_plugins((
+            GameCommonPlugin,
+            DeltaCompressionPlugin,
+            InterestPlugin,
+            PriorityPlugin,
+            LagCompensationPlugin,
+            DisconnectPlugin
+        ))
         .add_systems(Startup, server_setup_floor)
-        .add_systems(PreUpdate, ( 
-            handle_server_event,
+        // viewpoints despawned on disconnect are gone before reconnects are handled
+        .add_systems(PreUpdate, 
+            handle_server_event
+            .after(ServerSet::Receive)
+            .after(handle_disconnect_system)
+        )
+        .add_systems(FixedUpdate, (
             handle_fire,
             handle_force
         ).chain(
-        ).after(ServerSet::Receive))
+        ).after(apply_input_system
+        ).before(BEFORE_PHYSICS_SET))
         .add_systems(PostUpdate, 
             despawn_dropped
             .before(ServerSet::Send)
         )
-        .add_systems(FixedUpdate, 
-            set_network_rigidbody_system
-            .after(AFTER_PHYSICS_SET)
-        );
+        .add_systems(FixedUpdate, (
+            set_network_rigidbody_system,
+            release_idle_prediction_system
+        ).chain(
+        ).after(AFTER_PHYSICS_SET
+        ).before(update_network_rigidbody_delta_system));
     }
 }
 
 fn handle_server_event(
     mut commands: Commands,
-    mut events: EventReader<ServerEvent>
+    mut events: EventReader<ServerEvent>,
+    viewpoints: Query<&NetworkId>
 ) {
     for e in events.read() {
         match e {
             ServerEvent::ClientConnected { client_id } => {
+                info!("client: {client_id:?} connected");
+                // kept while the client was within its grace period
+                if viewpoints.iter().any(|n| n.client_id() == *client_id) {
+                    continue;
+                }
+
+                // also the viewpoint for interest management
                 commands.spawn((
                     Replicated,
-                    NetworkId::new(*client_id)
+                    NetworkId::new(*client_id),
+                    TransformBundle::from_transform(
+                        Transform::from_translation(CAMERA_POSITION)
+                    )
                 ));
-
-                info!("client: {client_id:?} connected");
             }
             ServerEvent::ClientDisconnected { client_id, reason } => {
                 info!(
@@ -53,50 +88,184 @@ fn handle_server_event(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn handle_fire(
     mut commands: Commands,
-    mut fire: EventReader<FromClient<NetworkFire>>
+    mut fire: EventReader<FromClient<NetworkFire>>,
+    mut rejected: EventWriter<ToClients<NetworkFireRejected>>,
+    mut last_spawn_ids: ResMut<LastSpawnIds>,
+    mut limits: ClientLimits,
+    viewpoints: Query<(&NetworkId, &Transform)>,
+    fixed_tick: Res<FixedTick>,
+    config: Res<FireConfig>
 ) {
-    for FromClient { client_id, event: _ } in fire.read() {
-        commands.spawn((
+    // balls accepted by this run, not visible to queries yet
+    let mut accepted = HashMap::<ClientId, usize>::new();
+
+    for FromClient { client_id, event } in fire.read() {
+        let viewpoint = viewpoints.iter()
+        .find(|(net_id, _)| net_id.client_id() == *client_id)
+        .map(|(_, transform)| transform.translation);
+        let is_reused = last_spawn_ids.is_reused(*client_id, event.spawn_id);
+
+        // every ball is a dynamic body on server, so spam is limited first
+        let result = if !limits.allow(*client_id, RateLimitedEvent::Fire) {
+            Err(FireRejection::RateLimited)
+        } else if !limits.allow_fire_ball(
+            *client_id, 
+            accepted.get(client_id).copied().unwrap_or_default()
+        ) {
+            Err(FireRejection::TooManyBalls)
+        } else {
+            match viewpoint {
+                _ if is_reused => Err(FireRejection::SpawnId),
+                Some(viewpoint) => config.spawn(event, viewpoint),
+                None => Err(FireRejection::NoViewpoint)
+            }
+        };
+        let (translation, velocity) = match result {
+            Ok(spawn) => spawn,
+            Err(rejection) => {
+                // violations of limits are logged by the abuse policy
+                if !matches!(rejection, FireRejection::RateLimited | FireRejection::TooManyBalls) {
+                    warn!(
+                        "rejected fire of client: {client_id:?} spawn id: {} reason: {rejection:?}", 
+                        event.spawn_id
+                    );
+                }
+                rejected.send(ToClients {
+                    mode: SendMode::Direct(*client_id),
+                    event: NetworkFireRejected{ spawn_id: event.spawn_id }
+                });
+                continue;
+            }
+        };
+        last_spawn_ids.insert(*client_id, event.spawn_id);
+        *accepted.entry(*client_id).or_default() += 1;
+
+        let net_rb = NetworkRigidBody::ClientPrediction { 
+            tick: fixed_tick.get(),
+            translation, 
+            rotation: BALL_SPAWN_ROTATION, 
+            velocity, 
+            angular_velocity: INITIAL_ANGULAR_VELOCITY 
+        }.with_mode(config.mode);
+        let is_client_authority = matches!(
+            net_rb, 
+            NetworkRigidBody::ClientAuthority { .. }
+        );
+
+        let mut entity = commands.spawn((
             Replicated,
-            NetworkFireBall::new(*client_id),
+            NetworkFireBall::new(*client_id, event.spawn_id),
             TransformBundle::from_transform(
                 Transform{
-                    translation: BALL_SPAWN_POSITION,
+                    translation,
                     rotation: BALL_SPAWN_ROTATION,
                     ..default()
                 }
             ),
-            // NetworkRigidBody::ServerSimulation { 
-            //     translation: BALL_SPAWN_POSITION, 
-            //     euler: BALL_SPAWN_EULER 
-            // },
-            NetworkRigidBody::ClientPrediction { 
-                translation: BALL_SPAWN_POSITION, 
-                euler: BALL_SPAWN_EULER, 
-                velocity: INITIAL_VELOCITY, 
-                angular_velocity: INITIAL_ANGULAR_VELOCITY 
-            },
-            generate_dynamic_ball(INITIAL_VELOCITY, INITIAL_ANGULAR_VELOCITY)
+            NetworkRigidBodyDelta::new(net_rb.clone()),
+            net_rb,
+            NetworkPriority::default(),
+            NetworkAuthority::new(Some(*client_id)),
+            AuthorityCooldown::default(),
+            LastInteraction::new(fixed_tick.get()),
+            ColliderHistory::default(),
+            DistanceCulling
         ));
+
+        // holder simulates the body, server only follows accepted states
+        if is_client_authority {
+            entity.insert((
+                AuthorityValidation::new(translation, fixed_tick.get()),
+                generate_kinematic_ball()
+            ));
+        } else {
+            entity.insert(
+                generate_dynamic_ball(velocity, INITIAL_ANGULAR_VELOCITY)
+            );
+        }
     }
 }
 
+#[allow(clippy::type_complexity)]
 fn handle_force(
     mut commands: Commands,
-    query: Query<(Entity, &NetworkFireBall)>,
-    mut force: EventReader<FromClient<NetworkForce>>
+    mut query: Query<(
+        &Transform,
+        &NetworkAuthority, 
+        &mut NetworkRigidBody,
+        &mut LastInteraction,
+        Option<&mut Sleeping>
+    )>,
+    mut force: EventReader<FromClient<NetworkForce>>,
+    mut last_force_ticks: ResMut<LastForceTicks>,
+    mut limits: ClientLimits,
+    fixed_tick: Res<FixedTick>,
+    config: Res<ForceConfig>
 ) {
-    for FromClient { client_id, event: _ } in force.read() {
-        for (e, ball) in query.iter() {
-            if ball.caster() == *client_id {
-                commands.entity(e)
-                .insert(ExternalImpulse{
-                    impulse: EXTRA_FORCE,
-                    torque_impulse: EXTRA_TORQUE
-                });
+    for FromClient { client_id, event } in force.read() {
+        // logged by the abuse policy
+        if !limits.allow(*client_id, RateLimitedEvent::Force) {
+            continue;
+        }
+
+        let Ok((
+            transform, 
+            authority, 
+            mut net_rb, 
+            mut last, 
+            sleeping
+        )) = query.get_mut(event.entity) else {
+            warn!("force of client: {client_id:?} targets missing entity: {:?}", event.entity);
+            continue;
+        };
+
+        // only the holder predicts the ball, and so only the holder can push it
+        let result = if !authority.is_held_by(*client_id) {
+            Err(ForceRejection::NotHolder)
+        } else if config.is_cooling_down(last_force_ticks.get(*client_id), fixed_tick.get()) {
+            Err(ForceRejection::Cooldown)
+        } else {
+            config.impulse(event, transform.translation)
+        };
+        let impulse = match result {
+            Ok(impulse) => impulse,
+            Err(rejection) => {
+                warn!(
+                    "rejected force of client: {client_id:?} on: {:?} reason: {rejection:?}",
+                    event.entity
+                );
+                continue;
             }
+        };
+        last_force_ticks.insert(*client_id, fixed_tick.get());
+
+        commands.entity(event.entity).insert(impulse);
+
+        // predicted while the holder interacts with it
+        last.touch(fixed_tick.get());
+        if net_rb.mode() == NetworkRigidBodyMode::ServerSimulation {
+            *net_rb = net_rb.with_mode(NetworkRigidBodyMode::ClientPrediction);
+        }
+
+        // resumes replication on the next fixed tick
+        if let Some(mut sleeping) = sleeping {
+            sleeping.sleeping = false;
+        }
+    }
+}
+
+fn release_idle_prediction_system(
+    mut query: Query<(&mut NetworkRigidBody, &LastInteraction)>,
+    fixed_tick: Res<FixedTick>
+) {
+    let release_ticks = (PREDICTION_RELEASE_SEC * PHYSICS_FIXED_TICK_RATE) as u32;
+    for (mut net_rb, last) in query.iter_mut() {
+        if net_rb.mode() == NetworkRigidBodyMode::ClientPrediction 
+        && fixed_tick.get().wrapping_sub(last.tick()) > release_ticks {
+            *net_rb = net_rb.with_mode(NetworkRigidBodyMode::ServerSimulation);
         }
     }
 }
@@ -113,36 +282,61 @@ fn despawn_dropped(
     }
 }
 
+#[allow(clippy::type_complexity)]
 fn set_network_rigidbody_system(
     mut query: Query<(
         Entity, 
         &Transform, 
         &mut NetworkRigidBody,
-        &Velocity
+        &Velocity,
+        Option<Ref<Sleeping>>
     ), 
         With<RigidBody>
-    >
+    >,
+    fixed_tick: Res<FixedTick>
 ) {
-    for (e, transform, mut net_rb, vel) in query.iter_mut() {
+    for (e, transform, mut net_rb, vel, sleeping) in query.iter_mut() {
+        // rest pose is written once when falling asleep
+        if sleeping.is_some_and(|s| s.sleeping && !s.is_changed()) {
+            continue;
+        }
+
         let trans = transform.translation;
         let rot = transform.rotation;
         
         match *net_rb {
-            NetworkRigidBody::ServerSimulation { ref mut translation, ref mut euler } => {
+            NetworkRigidBody::ServerSimulation { 
+                ref mut tick, 
+                ref mut translation, 
+                ref mut rotation,
+                ref mut velocity,
+                ref mut angular_velocity
+            } => {
+                *tick = fixed_tick.get();
                 *translation = trans;
-                *euler = quat_to_euler(rot);
+                *rotation = align_hemisphere(rot, *rotation);
+                if let Some(velocity) = velocity {
+                    *velocity = vel.linvel;
+                }
+                if let Some(angular_velocity) = angular_velocity {
+                    *angular_velocity = vel.angvel;
+                }
             }
             NetworkRigidBody::ClientPrediction { 
+                ref mut tick,
                 ref mut translation, 
-                ref mut euler,
+                ref mut rotation,
                 ref mut velocity,
                 ref mut angular_velocity, 
             } => {
+                *tick = fixed_tick.get();
                 *translation = trans;
                 *velocity = vel.linvel;
-                *euler = quat_to_euler(rot);
+                *rotation = align_hemisphere(rot, *rotation);
                 *angular_velocity = vel.angvel;
             }
+            // written by accepted states of the holder
+            NetworkRigidBody::ClientAuthority { .. } => continue
         }
         
         info!(
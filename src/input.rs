@@ -0,0 +1,388 @@
+use std::collections::VecDeque;
+use bevy::{
+    prelude::*,
+    ecs::entity::{EntityMapper, MapEntities},
+    utils::HashMap
+};
+use bevy_replicon::{prelude::*, client::server_entity_map::ServerEntityMap};
+use serde::{Serialize, Deserialize};
+use super::{
+    *,
+    config::*,
+    network_rigidbody::*,
+    rate_limit::*
+};
+
+pub const INPUT_FIRE: u8 = 1 << 0;
+pub const INPUT_FORCE: u8 = 1 << 1;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub struct InputFrame {
+    // server fixed tick the input is intended for
+    pub tick: u32,
+    pub buttons: u8,
+    // set on frames with INPUT_FIRE
+    pub fire: Option<NetworkFire>,
+    // set on frames with INPUT_FORCE
+    pub force: Option<NetworkForce>
+}
+
+// newest frames of a client, oldest first.
+// sent every fixed tick, also when nothing is pressed
+#[derive(Event, Serialize, Deserialize, Clone)]
+pub struct NetworkInput {
+    pub frames: Vec<InputFrame>
+}
+
+impl MapEntities for NetworkInput {
+    fn map_entities<M: EntityMapper>(&mut self, entity_mapper: &mut M) {
+        for force in self.frames.iter_mut().filter_map(|f| f.force.as_mut()) {
+            force.map_entities(entity_mapper);
+        }
+    }
+}
+
+// client only, buttons pressed since the last fixed tick
+#[derive(Resource, Default)]
+pub struct PendingInput {
+    buttons: u8,
+    fire: Option<NetworkFire>,
+    force: Option<NetworkForce>,
+    next_spawn_id: u32
+}
+
+impl PendingInput {
+    #[inline]
+    pub fn press(&mut self, buttons: u8) {
+        self.buttons |= buttons;
+    }
+
+    // spawn id is given when the frame is taken
+    #[inline]
+    pub fn fire(&mut self, origin: Vec3, direction: Vec3, charge: f32) {
+        self.buttons |= INPUT_FIRE;
+        self.fire = Some(NetworkFire{
+            spawn_id: 0,
+            origin,
+            direction,
+            charge
+        });
+    }
+
+    #[inline]
+    pub fn force(&mut self, force: NetworkForce) {
+        self.buttons |= INPUT_FORCE;
+        self.force = Some(force);
+    }
+
+    fn take_frame(&mut self, tick: u32) -> InputFrame {
+        let buttons = std::mem::take(&mut self.buttons);
+        let fire = self.fire.take().map(|fire| {
+            self.next_spawn_id = self.next_spawn_id.wrapping_add(1);
+            NetworkFire{
+                spawn_id: self.next_spawn_id,
+                ..fire
+            }
+        });
+
+        InputFrame{
+            tick,
+            buttons,
+            fire,
+            force: self.force.take()
+        }
+    }
+}
+
+// client only, newest frames sent, repeated by the next messages.
+// reconciliation does not replay these, impulses of the inputs are
+// kept by PredictionHistory of the body they were applied to
+#[derive(Resource, Default)]
+pub struct InputHistory {
+    frames: VecDeque<InputFrame>
+}
+
+impl InputHistory {
+    pub fn push(&mut self, frame: InputFrame) {
+        self.frames.push_back(frame);
+        while self.frames.len() > INPUT_REDUNDANCY {
+            self.frames.pop_front();
+        }
+    }
+
+    #[inline]
+    pub fn newest(&self, count: usize) -> impl Iterator<Item = &InputFrame> {
+        self.frames.iter()
+        .skip(self.frames.len().saturating_sub(count))
+    }
+}
+
+// server only, frames of a client not yet applied
+#[derive(Default)]
+pub struct InputBuffer {
+    frames: VecDeque<InputFrame>,
+    newest: Option<u32>,
+    late: u32,
+    min_depth: Option<i32>
+}
+
+impl InputBuffer {
+    // frames repeated by redundancy are dropped, and so are frames
+    // too far ahead of tick, which could block the ones after them
+    pub fn insert(&mut self, frame: InputFrame, tick: u32) -> bool {
+        if self.newest.is_some_and(|n| frame.tick.wrapping_sub(n) as i32 <= 0)
+        || frame.tick.wrapping_sub(tick) as i32 > INPUT_MAX_AHEAD_TICKS as i32
+        || self.frames.len() >= INPUT_BUFFER_MAX_LENGTH {
+            return false;
+        }
+
+        self.frames.push_back(frame);
+        self.newest = Some(frame.tick);
+        true
+    }
+
+    // every frame due at tick, frames that missed 
+    // their tick are applied late instead of lost
+    pub fn take_due(&mut self, tick: u32) -> Vec<InputFrame> {
+        let mut due = Vec::new();
+        while let Some(front) = self.frames.front() {
+            let ahead = front.tick.wrapping_sub(tick) as i32;
+            if ahead > 0 {
+                break;
+            }
+            if ahead < 0 {
+                self.late += 1;
+            }
+            due.extend(self.frames.pop_front());
+        }
+
+        if let Some(depth) = self.depth(tick) {
+            self.min_depth = Some(self.min_depth.map_or(depth, |d| d.min(depth)));
+        }
+        due
+    }
+
+    // ticks the newest frame arrived ahead of tick, negative when late
+    #[inline]
+    pub fn depth(&self, tick: u32) -> Option<i32> {
+        self.newest
+        .map(|n| n.wrapping_sub(tick) as i32)
+    }
+
+    #[inline]
+    pub fn late(&self) -> u32 {
+        self.late
+    }
+
+    // lowest depth since last taken, how close inputs came to being late
+    #[inline]
+    pub fn take_min_depth(&mut self) -> Option<i32> {
+        self.min_depth.take()
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct InputBuffers(HashMap<ClientId, InputBuffer>);
+
+impl InputBuffers {
+    #[inline]
+    pub fn get(&self, client_id: ClientId) -> Option<&InputBuffer> {
+        self.0.get(&client_id)
+    }
+
+    #[inline]
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&ClientId, &mut InputBuffer)> {
+        self.0.iter_mut()
+    }
+
+    #[inline]
+    pub fn remove(&mut self, client_id: ClientId) {
+        self.0.remove(&client_id);
+    }
+}
+
+pub struct InputPlugin;
+
+impl Plugin for InputPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PendingInput>()
+        .init_resource::<InputHistory>()
+        .init_resource::<InputBuffers>()
+        .add_mapped_client_event::<NetworkInput>(ChannelKind::Unreliable)
+        // emitted locally on the tick inputs are applied at
+        .add_event::<NetworkFire>()
+        .add_event::<NetworkForce>()
+        .add_event::<FromClient<NetworkFire>>()
+        .add_event::<FromClient<NetworkForce>>()
+        .add_systems(PreUpdate,
+            receive_input_system
+            .after(ServerSet::Receive)
+            .run_if(server_running)
+        )
+        .add_systems(FixedUpdate,
+            apply_input_system
+            .before(BEFORE_PHYSICS_SET)
+            .run_if(server_running)
+        )
+        .add_systems(FixedUpdate,
+            send_input_system
+            .before(BEFORE_PHYSICS_SET)
+            .run_if(client_connected)
+        );
+    }
+}
+
+fn receive_input_system(
+    mut inputs: EventReader<FromClient<NetworkInput>>,
+    mut buffers: ResMut<InputBuffers>,
+    mut limits: ClientLimits,
+    fixed_tick: Res<FixedTick>
+) {
+    for FromClient { client_id, event } in inputs.read() {
+        if !limits.allow(*client_id, RateLimitedEvent::Input) {
+            continue;
+        }
+
+        let buffer = buffers.0.entry(*client_id).or_default();
+        // honest clients never send more than the redundancy
+        let skipped = event.frames.len().saturating_sub(INPUT_REDUNDANCY);
+        for frame in event.frames.iter().skip(skipped) {
+            buffer.insert(*frame, fixed_tick.get());
+        }
+    }
+}
+
+pub(crate) fn apply_input_system(
+    mut buffers: ResMut<InputBuffers>,
+    mut fire: EventWriter<FromClient<NetworkFire>>,
+    mut force: EventWriter<FromClient<NetworkForce>>,
+    fixed_tick: Res<FixedTick>
+) {
+    for (client_id, buffer) in buffers.0.iter_mut() {
+        for frame in buffer.take_due(fixed_tick.get()) {
+            if let Some(event) = frame.fire {
+                fire.send(FromClient { client_id: *client_id, event });
+            }
+            if let Some(event) = frame.force {
+                force.send(FromClient { client_id: *client_id, event });
+            }
+        }
+    }
+}
+
+// inputs are applied locally on the same tick they are sent for,
+// so that predicted bodies see them at the tick server does
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn send_input_system(
+    mut pending: ResMut<PendingInput>,
+    mut history: ResMut<InputHistory>,
+    mut inputs: EventWriter<NetworkInput>,
+    mut fire: EventWriter<NetworkFire>,
+    mut force: EventWriter<NetworkForce>,
+    fixed_tick: Res<FixedTick>,
+    latest_tick: Res<LatestNetworkTick>,
+    entity_map: Res<ServerEntityMap>
+) {
+    let local_tick = fixed_tick.get();
+    let frame = pending.take_frame(
+        latest_tick.to_server_tick(local_tick)
+        .unwrap_or_else(|| latest_tick.estimated_fixed_tick(local_tick))
+    );
+    history.push(frame);
+
+    // forces on balls despawned since have no server entity to map to
+    inputs.send(NetworkInput{
+        frames: history.newest(INPUT_REDUNDANCY)
+        .map(|f| InputFrame{
+            force: f.force.filter(|force| {
+                entity_map.to_server().contains_key(&force.entity)
+            }),
+            ..*f
+        })
+        .collect()
+    });
+
+    if let Some(event) = frame.fire {
+        fire.send(event);
+    }
+    if let Some(event) = frame.force {
+        force.send(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(tick: u32, buttons: u8) -> InputFrame {
+        InputFrame{ tick, buttons, fire: None, force: None }
+    }
+
+    fn buttons(frames: Vec<InputFrame>) -> u8 {
+        frames.iter().fold(0, |b, f| b | f.buttons)
+    }
+
+    #[test]
+    fn redundant_frames_are_applied_once() {
+        let mut buffer = InputBuffer::default();
+        for f in [frame(10, 0), frame(11, INPUT_FIRE)] {
+            assert!(buffer.insert(f, 9));
+        }
+        // next message repeats the previous frames
+        for (f, inserted) in [(frame(10, 0), false), (frame(11, INPUT_FIRE), false), (frame(12, 0), true)] {
+            assert_eq!(buffer.insert(f, 9), inserted);
+        }
+
+        assert_eq!(buttons(buffer.take_due(10)), 0);
+        assert_eq!(buttons(buffer.take_due(11)), INPUT_FIRE);
+        assert!(buffer.take_due(11).is_empty());
+        assert_eq!(buffer.depth(11), Some(1));
+    }
+
+    #[test]
+    fn late_frames_are_applied_on_the_next_tick() {
+        let mut buffer = InputBuffer::default();
+        buffer.insert(frame(5, INPUT_FORCE), 4);
+        buffer.insert(frame(6, INPUT_FIRE), 4);
+
+        assert_eq!(buttons(buffer.take_due(8)), INPUT_FORCE | INPUT_FIRE);
+        assert_eq!(buffer.late(), 2);
+        assert_eq!(buffer.depth(8), Some(-2));
+        assert_eq!(buffer.take_min_depth(), Some(-2));
+        assert_eq!(buffer.take_min_depth(), None);
+    }
+
+    #[test]
+    fn far_future_frames_are_dropped() {
+        let mut buffer = InputBuffer::default();
+        let ahead = 10 + INPUT_MAX_AHEAD_TICKS;
+
+        assert!(!buffer.insert(frame(ahead + 1, INPUT_FIRE), 10));
+        assert_eq!(buffer.depth(10), None);
+        assert!(buffer.insert(frame(11, 0), 10));
+        assert!(buffer.insert(frame(ahead, INPUT_FIRE), 10));
+        assert_eq!(buttons(buffer.take_due(11)), 0);
+
+        // buffer length is bounded also while server is not taking frames
+        for tick in ahead + 1.. {
+            if !buffer.insert(frame(tick, 0), tick) {
+                break;
+            }
+        }
+        assert_eq!(buffer.frames.len(), INPUT_BUFFER_MAX_LENGTH);
+    }
+
+    #[test]
+    fn fire_frames_carry_new_spawn_ids() {
+        let mut pending = PendingInput::default();
+        pending.fire(Vec3::ZERO, Vec3::NEG_Y, 1.0);
+        let first = pending.take_frame(1);
+        assert_eq!(pending.take_frame(2).fire, None);
+        pending.fire(Vec3::ZERO, Vec3::NEG_Y, 0.5);
+        pending.press(INPUT_FORCE);
+        let second = pending.take_frame(3);
+
+        assert_eq!(second.buttons, INPUT_FIRE | INPUT_FORCE);
+        assert_ne!(first.fire.map(|f| f.spawn_id), second.fire.map(|f| f.spawn_id));
+    }
+}
@@ -0,0 +1,79 @@
+use bevy::{prelude::*, transform::TransformSystem};
+use bevy_replicon::prelude::*;
+use super::{
+    *,
+    config::*,
+    delta::*
+};
+
+// server only, replicated only to clients whose viewpoint is close enough.
+// replicated entities without this are visible to every client
+#[derive(Component)]
+pub struct DistanceCulling;
+
+#[derive(Resource)]
+pub struct InterestConfig {
+    pub distance: f32,
+    pub hysteresis: f32
+}
+
+impl Default for InterestConfig {
+    fn default() -> Self {
+        Self{
+            distance: DISTANCE_CULLING_THREASHOLD,
+            hysteresis: DISTANCE_CULLING_HYSTERESIS
+        }
+    }
+}
+
+pub struct InterestPlugin;
+
+impl Plugin for InterestPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<InterestConfig>()
+        .add_systems(PostUpdate, 
+            update_client_visibility_system
+            .after(TransformSystem::TransformPropagate)
+            .before(update_network_rigidbody_delta_mask_system)
+            .before(ServerSet::Send)
+        );
+    }
+}
+
+// viewpoint of each client is the transform of its NetworkId entity.
+// server spawns it at CAMERA_POSITION and never moves it,
+// since clients render from that fixed camera
+fn update_client_visibility_system(
+    viewpoints: Query<(&NetworkId, &GlobalTransform)>,
+    culled: Query<(Entity, &GlobalTransform), With<DistanceCulling>>,
+    unculled: Query<Entity, (With<Replicated>, Without<DistanceCulling>)>,
+    mut connected_clients: ResMut<ConnectedClients>,
+    config: Res<InterestConfig>
+) {
+    let enter = config.distance * config.distance;
+    let exit = (config.distance + config.hysteresis).powi(2);
+
+    for (net_id, viewpoint) in viewpoints.iter() {
+        let Some(client) = connected_clients.get_client_mut(net_id.client_id()) else {
+            continue;
+        };
+        let visibility = client.visibility_mut();
+
+        for e in unculled.iter() {
+            if !visibility.is_visible(e) {
+                visibility.set_visibility(e, true);
+            }
+        }
+
+        for (e, transform) in culled.iter() {
+            let distance = transform.translation()
+            .distance_squared(viewpoint.translation());
+            let visible = visibility.is_visible(e);
+            if !visible && distance < enter {
+                visibility.set_visibility(e, true);
+            } else if visible && distance > exit {
+                visibility.set_visibility(e, false);
+            }
+        }
+    }
+}
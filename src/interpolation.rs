@@ -0,0 +1,586 @@
+use std::collections::VecDeque;
+use bevy::prelude::*;
+use bevy_replicon::{
+    prelude::*,
+    client::ClientSet,
+    core::replicon_tick::RepliconTick
+};
+use bevy_replicon_renet::renet::RenetClient;
+use super::{
+    *,
+    config::*,
+    network_rigidbody::*
+};
+
+#[derive(Clone, Copy)]
+pub struct Snapshot {
+    pub tick: u32,
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub velocity: Option<Vec3>,
+    pub angular_velocity: Option<Vec3>
+}
+
+impl Snapshot {
+    pub fn from_net_rb(net_rb: &NetworkRigidBody) -> Self {
+        let (translation, rotation, velocity, angular_velocity) = match *net_rb {
+            NetworkRigidBody::ServerSimulation { 
+                translation, 
+                rotation, 
+                velocity, 
+                angular_velocity, 
+                .. 
+            } => (translation, rotation, velocity, angular_velocity),
+            NetworkRigidBody::ClientPrediction { 
+                translation, 
+                rotation, 
+                velocity, 
+                angular_velocity, 
+                .. 
+            } 
+            | NetworkRigidBody::ClientAuthority { 
+                translation, 
+                rotation, 
+                velocity, 
+                angular_velocity, 
+                .. 
+            } => (translation, rotation, Some(velocity), Some(angular_velocity))
+        };
+
+        Self{
+            tick: net_rb.tick(),
+            translation,
+            rotation,
+            velocity,
+            angular_velocity
+        }
+    }
+}
+
+// snapshots sorted by server fixed tick, oldest first
+#[derive(Component)]
+pub struct SnapshotBuffer {
+    snapshots: VecDeque<Snapshot>,
+    capacity: usize
+}
+
+impl SnapshotBuffer {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self{
+            snapshots: VecDeque::with_capacity(capacity),
+            capacity: capacity.max(2)
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    #[inline]
+    pub fn newest(&self) -> Option<&Snapshot> {
+        self.snapshots.back()
+    }
+
+    // returns false for duplicates and for snapshots
+    // older than everything a full buffer holds
+    pub fn insert(&mut self, snapshot: Snapshot) -> bool {
+        let index = self.snapshots
+        .partition_point(|s| s.tick < snapshot.tick);
+        if let Some(s) = self.snapshots.get(index) {
+            if s.tick == snapshot.tick {
+                return false;
+            }
+        }
+        if index == 0 && self.snapshots.len() >= self.capacity {
+            return false;
+        }
+
+        self.snapshots.insert(index, snapshot);
+        // wire format does not keep the sign of rotation
+        for i in index.max(1)..self.snapshots.len() {
+            let reference = self.snapshots[i - 1].rotation;
+            let s = &mut self.snapshots[i];
+            s.rotation = align_hemisphere(s.rotation, reference);
+        }
+        while self.snapshots.len() > self.capacity {
+            self.snapshots.pop_front();
+        }
+        true
+    }
+
+    // drops snapshots that can not bracket render tick anymore
+    pub fn prune(&mut self, render_tick: f64) {
+        while self.snapshots.len() > 2 {
+            if (self.snapshots[1].tick as f64) <= render_tick {
+                self.snapshots.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+
+    #[inline]
+    pub fn is_beyond(&self, render_tick: f64) -> bool {
+        self.snapshots.back()
+        .is_some_and(|s| (s.tick as f64) < render_tick)
+    }
+
+    // dead reckoning from the newest snapshot, bounded by max ticks
+    pub fn extrapolate(&self, render_tick: f64, max_ticks: f64) -> Option<(Vec3, Quat)> {
+        let newest = self.snapshots.back()?;
+        let ticks = (render_tick - newest.tick as f64)
+        .clamp(0.0, max_ticks);
+        let delta = (ticks * PHYSICS_FIXED_TICK_DELTA as f64) as f32;
+
+        let translation = match newest.velocity {
+            Some(velocity) => newest.translation + velocity * delta,
+            None => newest.translation
+        };
+        let rotation = match newest.angular_velocity {
+            Some(angular_velocity) 
+            => Quat::from_scaled_axis(angular_velocity * delta) * newest.rotation,
+            None => newest.rotation
+        };
+        Some((translation, rotation.normalize()))
+    }
+
+    // interpolates between the two snapshots bracketing render tick,
+    // holds the nearest one outside of the buffer
+    pub fn sample(&self, render_tick: f64, mode: InterpolationMode) 
+    -> Option<(Vec3, Quat)> {
+        let index = self.snapshots
+        .partition_point(|s| (s.tick as f64) <= render_tick);
+
+        if index == 0 {
+            return self.snapshots.front()
+            .map(|s| (s.translation, s.rotation));
+        }
+        if index == self.snapshots.len() {
+            return self.snapshots.back()
+            .map(|s| (s.translation, s.rotation));
+        }
+
+        let from = &self.snapshots[index - 1];
+        let to = &self.snapshots[index];
+        let span = to.tick.wrapping_sub(from.tick) as f64;
+        let per = ((render_tick - from.tick as f64) / span)
+        .clamp(0.0, 1.0) as f32;
+
+        if mode == InterpolationMode::Hermite {
+            let delta = (span * PHYSICS_FIXED_TICK_DELTA as f64) as f32;
+            if let Some(sampled) = hermite(from, to, per, delta) {
+                return Some(sampled);
+            }
+        }
+
+        Some((
+            from.translation.lerp(to.translation, per),
+            from.rotation.slerp(to.rotation, per)
+        ))
+    }
+}
+
+// cubic hermite translation, and rotation integrated from both ends 
+// by angular velocity before slerp, needs velocities of both snapshots
+fn hermite(from: &Snapshot, to: &Snapshot, per: f32, delta: f32) 
+-> Option<(Vec3, Quat)> {
+    let (v0, v1) = (from.velocity?, to.velocity?);
+    let per2 = per * per;
+    let per3 = per2 * per;
+    let h00 = 2.0 * per3 - 3.0 * per2 + 1.0;
+    let h10 = per3 - 2.0 * per2 + per;
+    let h01 = -2.0 * per3 + 3.0 * per2;
+    let h11 = per3 - per2;
+    let translation = from.translation * h00 
+    + v0 * (h10 * delta)
+    + to.translation * h01
+    + v1 * (h11 * delta);
+
+    let rotation = match (from.angular_velocity, to.angular_velocity) {
+        (Some(w0), Some(w1)) => {
+            let forward = Quat::from_scaled_axis(w0 * (per * delta)) 
+            * from.rotation;
+            let backward = Quat::from_scaled_axis(-w1 * ((1.0 - per) * delta)) 
+            * to.rotation;
+            forward.normalize().slerp(backward.normalize(), per)
+        }
+        _ => from.rotation.slerp(to.rotation, per)
+    };
+
+    Some((translation, rotation))
+}
+
+#[derive(Component, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InterpolationMode {
+    #[default]
+    Linear,
+    // falls back to linear while snapshots carry no velocity
+    Hermite
+}
+
+#[derive(Resource, Clone)]
+pub struct InterpolationConfig {
+    pub buffer_length: usize,
+    // network ticks to render behind the newest state at start
+    pub delay_network_ticks: f32,
+    pub min_delay_network_ticks: f32,
+    pub max_delay_network_ticks: f32,
+    pub jitter_scale: f32,
+    pub loss_scale: f32,
+    pub delay_grow_rate: f32,
+    pub delay_shrink_rate: f32,
+    pub max_extrapolation_sec: f32,
+    pub extrapolation_blend_rate: f32,
+    // per fixed tick pull of render time towards its target
+    pub time_correction: f64
+}
+
+impl Default for InterpolationConfig {
+    fn default() -> Self {
+        Self{
+            buffer_length: INTERPOLATION_BUFFER_LENGTH,
+            delay_network_ticks: INTERPOLATION_DELAY_NETWORK_TICKS,
+            min_delay_network_ticks: INTERPOLATION_MIN_DELAY_NETWORK_TICKS,
+            max_delay_network_ticks: INTERPOLATION_MAX_DELAY_NETWORK_TICKS,
+            jitter_scale: INTERPOLATION_JITTER_SCALE,
+            loss_scale: INTERPOLATION_LOSS_SCALE,
+            delay_grow_rate: INTERPOLATION_DELAY_GROW_RATE,
+            delay_shrink_rate: INTERPOLATION_DELAY_SHRINK_RATE,
+            max_extrapolation_sec: EXTRAPOLATION_MAX_SEC,
+            extrapolation_blend_rate: EXTRAPOLATION_BLEND_RATE,
+            time_correction: INTERPOLATION_TIME_CORRECTION
+        }
+    }
+}
+
+impl InterpolationConfig {
+    #[inline]
+    pub fn max_extrapolation_ticks(&self) -> f64 {
+        (self.max_extrapolation_sec * PHYSICS_FIXED_TICK_RATE) as f64
+    }
+}
+
+// rendered error left when extrapolation is replaced by fresh snapshots
+#[derive(Component)]
+pub struct ExtrapolationBlend {
+    translation: Vec3,
+    rotation: Quat,
+    extrapolating: bool
+}
+
+impl Default for ExtrapolationBlend {
+    fn default() -> Self {
+        Self{
+            translation: Vec3::ZERO,
+            rotation: Quat::IDENTITY,
+            extrapolating: false
+        }
+    }
+}
+
+impl ExtrapolationBlend {
+    // rendered pose starts away from the samples, e.g. after switching modes
+    #[inline]
+    pub fn from_offset(translation: Vec3, rotation: Quat) -> Self {
+        Self{
+            translation,
+            rotation,
+            extrapolating: false
+        }
+    }
+
+    #[inline]
+    pub fn is_extrapolating(&self) -> bool {
+        self.extrapolating
+    }
+}
+
+// current interpolation delay and the measurements driving it
+#[derive(Resource)]
+pub struct InterpolationDelay {
+    network_ticks: f32,
+    jitter: f32,
+    loss: f32
+}
+
+impl FromWorld for InterpolationDelay {
+    fn from_world(world: &mut World) -> Self {
+        let config = world.resource::<InterpolationConfig>();
+        Self{
+            network_ticks: config.delay_network_ticks,
+            jitter: 0.0,
+            loss: 0.0
+        }
+    }
+}
+
+impl InterpolationDelay {
+    #[inline]
+    pub fn network_ticks(&self) -> f32 {
+        self.network_ticks
+    }
+
+    #[inline]
+    pub fn seconds(&self) -> f32 {
+        self.network_ticks * DEV_NETWORK_TICK_DELTA
+    }
+
+    #[inline]
+    pub fn fixed_ticks(&self) -> f64 {
+        (self.seconds() * PHYSICS_FIXED_TICK_RATE) as f64
+    }
+
+    // smoothed deviation of update transit time in seconds
+    #[inline]
+    pub fn jitter(&self) -> f32 {
+        self.jitter
+    }
+
+    // smoothed ratio of lost packets
+    #[inline]
+    pub fn loss(&self) -> f32 {
+        self.loss
+    }
+
+    // deviation is the change of transit time since the previous update.
+    // loss is what renet measures by acks of packet sequence numbers,
+    // taken for both directions. gaps between server ticks of updates
+    // are mostly bodies sleeping, culled or out of budget instead
+    pub fn add_sample(&mut self, deviation: f32, loss: f32, config: &InterpolationConfig) {
+        // RFC 3550 style interarrival jitter
+        self.jitter += (deviation - self.jitter) / 16.0;
+        self.loss += (loss - self.loss) * 0.1;
+
+        let target = (1.0 
+            + self.jitter * config.jitter_scale / DEV_NETWORK_TICK_DELTA
+            + self.loss * config.loss_scale
+        ).clamp(
+            config.min_delay_network_ticks, 
+            config.max_delay_network_ticks
+        );
+        let rate = if target > self.network_ticks {
+            config.delay_grow_rate
+        } else {
+            config.delay_shrink_rate
+        };
+        self.network_ticks += (target - self.network_ticks) * rate;
+    }
+}
+
+// server fixed tick currently rendered, advances one per local fixed tick
+#[derive(Resource, Default)]
+pub struct InterpolationTime(Option<f64>);
+
+impl InterpolationTime {
+    #[inline]
+    pub fn render_tick(&self) -> Option<f64> {
+        self.0
+    }
+}
+
+pub struct InterpolationPlugin;
+
+impl Plugin for InterpolationPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<InterpolationConfig>()
+        .init_resource::<InterpolationDelay>()
+        .init_resource::<InterpolationTime>()
+        .add_systems(PreUpdate, 
+            measure_interpolation_delay_system
+            .after(ClientSet::Receive)
+            .after(update_latest_network_tick_system)
+            .run_if(client_connected)
+        )
+        .add_systems(FixedUpdate, (
+            update_snapshot_buffer_system,
+            advance_interpolation_time_system,
+            apply_snapshot_interpolation_system
+        ).chain(
+        ).before(BEFORE_PHYSICS_SET));
+    }
+}
+
+fn update_snapshot_buffer_system(
+    mut query: Query<
+        (&NetworkRigidBody, &mut SnapshotBuffer),
+        Changed<NetworkRigidBodyTick>
+    >
+) {
+    for (net_rb, mut buffer) in query.iter_mut() {
+        buffer.insert(Snapshot::from_net_rb(net_rb));
+    }
+}
+
+fn measure_interpolation_delay_system(
+    mut delay: ResMut<InterpolationDelay>,
+    mut last: Local<Option<(RepliconTick, f64)>>,
+    client: Res<RenetClient>,
+    config: Res<InterpolationConfig>,
+    latest_tick: Res<LatestNetworkTick>,
+    time: Res<Time<Real>>
+) {
+    if !latest_tick.is_changed() || !latest_tick.has_received() {
+        return;
+    }
+
+    let server_tick = latest_tick.server_tick();
+    let transit = time.elapsed_seconds_f64()
+    - latest_tick.fixed_tick() as f64 * PHYSICS_FIXED_TICK_DELTA as f64;
+
+    if let Some((last_server_tick, last_transit)) = *last {
+        if server_tick <= last_server_tick {
+            return;
+        }
+
+        let deviation = (transit - last_transit).abs() as f32;
+        delay.add_sample(deviation, client.packet_loss() as f32, &config);
+    }
+
+    *last = Some((server_tick, transit));
+}
+
+fn advance_interpolation_time_system(
+    mut interpolation_time: ResMut<InterpolationTime>,
+    interpolation_delay: Res<InterpolationDelay>,
+    config: Res<InterpolationConfig>,
+    latest_tick: Res<LatestNetworkTick>,
+    fixed_tick: Res<FixedTick>
+) {
+    if !latest_tick.has_received() {
+        return;
+    }
+
+    let delay = interpolation_delay.fixed_ticks();
+    let target = latest_tick.estimated_fixed_tick(fixed_tick.get()) as f64 - delay;
+    interpolation_time.0 = Some(match interpolation_time.0 {
+        // jumps only when too far away to catch up smoothly
+        Some(render_tick) if (target - render_tick).abs() < delay => {
+            let next = render_tick + 1.0;
+            next + (target - next) * config.time_correction
+        }
+        _ => target
+    });
+}
+
+fn apply_snapshot_interpolation_system(
+    mut query: Query<(
+        &mut SnapshotBuffer,
+        &mut ExtrapolationBlend,
+        &mut Transform,
+        Option<&InterpolationMode>
+    )>,
+    interpolation_time: Res<InterpolationTime>,
+    config: Res<InterpolationConfig>
+) {
+    let Some(render_tick) = interpolation_time.render_tick() else {
+        return;
+    };
+
+    for (mut buffer, mut blend, mut transform, mode) in query.iter_mut() {
+        buffer.prune(render_tick);
+
+        let extrapolating = buffer.is_beyond(render_tick);
+        let sample = if extrapolating {
+            buffer.extrapolate(render_tick, config.max_extrapolation_ticks())
+        } else {
+            buffer.sample(render_tick, mode.copied().unwrap_or_default())
+        };
+        let Some((translation, rotation)) = sample else {
+            continue;
+        };
+
+        if blend.extrapolating && !extrapolating {
+            blend.translation = transform.translation - translation;
+            blend.rotation = transform.rotation * rotation.inverse();
+        }
+        blend.extrapolating = extrapolating;
+
+        let decay = 1.0 - config.extrapolation_blend_rate;
+        blend.translation *= decay;
+        blend.rotation = Quat::IDENTITY.slerp(blend.rotation, decay);
+
+        transform.translation = translation + blend.translation;
+        transform.rotation = blend.rotation * rotation;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(tick: u32, x: f32) -> Snapshot {
+        Snapshot{
+            tick,
+            translation: Vec3::X * x,
+            rotation: Quat::IDENTITY,
+            velocity: Some(Vec3::X * PHYSICS_FIXED_TICK_RATE),
+            angular_velocity: None
+        }
+    }
+
+    #[test]
+    fn buffer_keeps_snapshots_sorted() {
+        let mut buffer = SnapshotBuffer::with_capacity(3);
+
+        assert!(buffer.insert(snapshot(20, 20.0)));
+        assert!(buffer.insert(snapshot(10, 10.0)));
+        assert!(!buffer.insert(snapshot(10, 10.0)));
+        assert!(buffer.insert(snapshot(30, 30.0)));
+        // older than everything the full buffer holds
+        assert!(!buffer.insert(snapshot(5, 5.0)));
+        assert!(buffer.insert(snapshot(25, 25.0)));
+
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(buffer.newest().map(|s| s.tick), Some(30));
+        buffer.prune(26.0);
+        assert_eq!(buffer.len(), 2);
+    }
+
+    #[test]
+    fn buffer_samples_and_extrapolates() {
+        let mut buffer = SnapshotBuffer::with_capacity(4);
+        buffer.insert(snapshot(10, 10.0));
+        buffer.insert(snapshot(20, 20.0));
+
+        let (translation, _) = buffer.sample(15.0, InterpolationMode::Linear).unwrap();
+        assert!((translation.x - 15.0).abs() < 1e-4);
+        let (translation, _) = buffer.sample(5.0, InterpolationMode::Linear).unwrap();
+        assert_eq!(translation.x, 10.0);
+
+        assert!(buffer.is_beyond(22.0));
+        // one unit per tick, bounded by max ticks
+        let (translation, _) = buffer.extrapolate(22.0, 10.0).unwrap();
+        assert!((translation.x - 22.0).abs() < 1e-3);
+        let (translation, _) = buffer.extrapolate(100.0, 5.0).unwrap();
+        assert!((translation.x - 25.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn delay_follows_jitter_and_loss() {
+        let config = InterpolationConfig::default();
+        let mut delay = InterpolationDelay{
+            network_ticks: config.delay_network_ticks,
+            jitter: 0.0,
+            loss: 0.0
+        };
+
+        for _ in 0..1000 {
+            delay.add_sample(0.0, 0.0, &config);
+        }
+        let steady = delay.network_ticks();
+        assert!(steady >= config.min_delay_network_ticks);
+
+        for _ in 0..1000 {
+            delay.add_sample(DEV_NETWORK_TICK_DELTA, 0.2, &config);
+        }
+        assert!(delay.jitter() > 0.0 && delay.loss() > 0.1);
+        assert!(delay.network_ticks() > steady);
+        assert!(delay.network_ticks() <= config.max_delay_network_ticks);
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::VecDeque;
+use bevy::{prelude::*, ecs::system::SystemParam};
+use bevy_rapier3d::{
+    prelude::*,
+    rapier::{geometry::ColliderHandle, math::Isometry}
+};
+use super::{
+    *,
+    config::*,
+    network_rigidbody::*
+};
+
+// server only, poses of the collider after each fixed tick
+#[derive(Component, Default)]
+pub struct ColliderHistory {
+    poses: VecDeque<(u32, Vec3, Quat)>
+}
+
+impl ColliderHistory {
+    pub fn push(&mut self, tick: u32, translation: Vec3, rotation: Quat, max_len: usize) {
+        self.poses.push_back((tick, translation, rotation));
+        while self.poses.len() > max_len {
+            self.poses.pop_front();
+        }
+    }
+
+    // interpolated between the ticks around,
+    // None when the tick is out of the recorded window
+    pub fn pose_at(&self, tick: f64) -> Option<(Vec3, Quat)> {
+        let i = self.poses.iter()
+        .position(|(t, _, _)| *t as f64 >= tick)?;
+        let (to_tick, to_translation, to_rotation) = self.poses[i];
+        if to_tick as f64 == tick {
+            return Some((to_translation, to_rotation));
+        }
+
+        let (from_tick, from_translation, from_rotation) = *self.poses.get(i.checked_sub(1)?)?;
+        let t = ((tick - from_tick as f64) / (to_tick as f64 - from_tick as f64)) as f32;
+        Some((
+            from_translation.lerp(to_translation, t),
+            from_rotation.slerp(to_rotation, t)
+        ))
+    }
+}
+
+#[derive(Resource)]
+pub struct LagCompensationConfig {
+    pub max_rewind_ticks: u32
+}
+
+impl Default for LagCompensationConfig {
+    fn default() -> Self {
+        Self{
+            max_rewind_ticks: (LAG_COMPENSATION_MAX_REWIND_SEC * PHYSICS_FIXED_TICK_RATE) as u32
+        }
+    }
+}
+
+// server only, casts against colliders with history
+// as they were at a past server tick, e.g. the render tick of a client.
+// colliders without history, like the floor, are cast where they are
+#[derive(SystemParam)]
+pub struct LagCompensation<'w, 's> {
+    context: ResMut<'w, RapierContext>,
+    histories: Query<'w, 's, (Entity, &'static ColliderHistory)>,
+    fixed_tick: Res<'w, FixedTick>,
+    config: Res<'w, LagCompensationConfig>
+}
+
+impl LagCompensation<'_, '_> {
+    // requests older than the rewind window are cast at its oldest tick
+    #[inline]
+    pub fn clamp_tick(&self, tick: f64) -> f64 {
+        let current = self.fixed_tick.get() as f64;
+        tick.clamp(current - self.config.max_rewind_ticks as f64, current)
+    }
+
+    // moves colliders back until the returned context is dropped.
+    // casts of the same tick should share one, since rewinding and
+    // restoring both update the query pipeline
+    pub fn rewind(&mut self, tick: f64) -> RewoundContext<'_> {
+        let tick = self.clamp_tick(tick);
+        let context = &mut *self.context;
+        let mut presents = Vec::new();
+        for (e, history) in self.histories.iter() {
+            let Some((translation, rotation)) = history.pose_at(tick) else {
+                continue;
+            };
+            let Some(&handle) = context.entity2collider().get(&e) else {
+                continue;
+            };
+            let Some(collider) = context.colliders.get_mut(handle) else {
+                continue;
+            };
+
+            presents.push((handle, *collider.position()));
+            collider.set_position(Isometry::from_parts(
+                translation.into(),
+                rotation.into()
+            ));
+        }
+        context.update_query_pipeline();
+
+        RewoundContext{
+            context,
+            presents
+        }
+    }
+
+    #[inline]
+    pub fn with_rewound<R>(&mut self, tick: f64, query: impl FnOnce(&RapierContext) -> R) -> R {
+        query(&self.rewind(tick))
+    }
+}
+
+// rapier context with colliders at a past tick, 
+// restored to their present poses on drop
+pub struct RewoundContext<'a> {
+    context: &'a mut RapierContext,
+    presents: Vec<(ColliderHandle, Isometry<f32>)>
+}
+
+impl std::ops::Deref for RewoundContext<'_> {
+    type Target = RapierContext;
+
+    fn deref(&self) -> &Self::Target {
+        self.context
+    }
+}
+
+impl Drop for RewoundContext<'_> {
+    fn drop(&mut self) {
+        for (handle, position) in self.presents.drain(..) {
+            if let Some(collider) = self.context.colliders.get_mut(handle) {
+                collider.set_position(position);
+            }
+        }
+        self.context.update_query_pipeline();
+    }
+}
+
+pub struct LagCompensationPlugin;
+
+impl Plugin for LagCompensationPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<LagCompensationConfig>()
+        .add_systems(FixedUpdate,
+            record_collider_history_system
+            .after(AFTER_PHYSICS_SET)
+        );
+    }
+}
+
+fn record_collider_history_system(
+    mut query: Query<(&Transform, &mut ColliderHistory)>,
+    fixed_tick: Res<FixedTick>,
+    config: Res<LagCompensationConfig>
+) {
+    // window plus the current tick and one before it,
+    // so that the oldest tick of the window can be interpolated into
+    let max_len = config.max_rewind_ticks as usize + 2;
+    for (transform, mut history) in query.iter_mut() {
+        history.push(
+            fixed_tick.get(),
+            transform.translation,
+            transform.rotation,
+            max_len
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pose_is_interpolated_within_window() {
+        let mut history = ColliderHistory::default();
+        for tick in 10..14 {
+            history.push(tick, Vec3::X * tick as f32, Quat::IDENTITY, 3);
+        }
+
+        assert_eq!(history.pose_at(12.0), Some((Vec3::X * 12.0, Quat::IDENTITY)));
+        assert_eq!(history.pose_at(12.5).map(|p| p.0), Some(Vec3::X * 12.5));
+        // ticks before the oldest kept one are out of the window
+        assert_eq!(history.pose_at(10.5), None);
+        assert_eq!(history.pose_at(14.0), None);
+    }
+
+    #[test]
+    fn casts_hit_rewound_colliders_until_restored() {
+        use bevy::ecs::system::RunSystemOnce;
+
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            TransformPlugin,
+            RapierPhysicsPlugin::<NoUserData>::default()
+        ))
+        .init_resource::<FixedTick>()
+        .init_resource::<LagCompensationConfig>();
+
+        let mut history = ColliderHistory::default();
+        history.push(0, Vec3::X * 10.0, Quat::IDENTITY, 2);
+        let e = app.world.spawn((
+            TransformBundle::default(),
+            Collider::ball(1.0),
+            history
+        )).id();
+        app.update();
+
+        let hits = app.world.run_system_once(move |mut lag: LagCompensation| {
+            let cast = |context: &RapierContext, x: f32| context.cast_ray(
+                Vec3::new(x, 10.0, 0.0), 
+                Vec3::NEG_Y, 
+                20.0, 
+                true, 
+                QueryFilter::default()
+            ).map(|(e, _)| e);
+
+            let rewound = lag.rewind(0.0);
+            let past = (cast(&rewound, 10.0), cast(&rewound, 0.0));
+            drop(rewound);
+            let present = (cast(&lag.context, 10.0), cast(&lag.context, 0.0));
+            (past, present)
+        });
+        assert_eq!(hits, ((Some(e), None), (None, Some(e))));
+    }
+}
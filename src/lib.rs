@@ -5,17 +5,43 @@ pub mod client_builder;
 pub mod game_server;
 pub mod game_client;
 pub mod network_rigidbody;
+pub mod prediction;
+pub mod interpolation;
+pub mod quantization;
+pub mod delta;
+pub mod interest;
+pub mod priority;
+pub mod client_authority;
+pub mod authority;
+pub mod input;
+pub mod clock;
+pub mod dilation;
+pub mod lag_compensation;
+pub mod fire;
+pub mod force;
+pub mod rate_limit;
+pub mod disconnect;
 
 use bevy_replicon::prelude::AppRuleExt;
 use serde::{Deserialize, Serialize};
-use bevy::prelude::*;
+use bevy::{
+    prelude::*,
+    ecs::entity::{EntityMapper, MapEntities}
+};
 use bevy_replicon::prelude::*;
 use bevy_rapier3d::prelude::*;
 use config::*;
 use network_rigidbody::*;
+use client_authority::*;
+use authority::*;
+use input::*;
+use clock::*;
+use dilation::*;
+use fire::*;
+use force::*;
+use rate_limit::*;
 
 pub const BALL_SPAWN_POSITION: Vec3 = Vec3::new(0.0, 15.0, 0.0);
-pub const BALL_SPAWN_EULER: Vec3 = Vec3::new(0.0, 0.0, 0.0);
 pub const BALL_SPAWN_ROTATION: Quat = Quat::IDENTITY;
 pub const BALL_RADIUS: f32 = 1.0;
 pub const BALL_RESTITUTION: f32 = 0.8;
@@ -23,8 +49,6 @@ pub const BALL_COLOR: Color = Color::RED;
 
 pub const INITIAL_VELOCITY: Vec3 = Vec3::new(0.0, 10.0, 0.0);
 pub const INITIAL_ANGULAR_VELOCITY: Vec3 = Vec3::new(5.0, 5.0, 0.0);
-pub const EXTRA_FORCE: Vec3 = Vec3::new(0.0, 100.0, 0.0);
-pub const EXTRA_TORQUE: Vec3 = Vec3::new(0.0, 0.0, 5.0);
 
 pub const DROPPED_Y: f32 = -15.0;
 
@@ -51,31 +75,65 @@ impl NetworkId {
 }
 
 #[derive(Component, Serialize, Deserialize)]
-pub struct NetworkFireBall(ClientId);
+pub struct NetworkFireBall {
+    caster: ClientId,
+    spawn_id: u32
+}
 
 impl NetworkFireBall {
     #[inline]
-    pub fn new(caster: ClientId) -> Self {
-        Self(caster)
+    pub fn new(caster: ClientId, spawn_id: u32) -> Self {
+        Self{
+            caster,
+            spawn_id
+        }
     }
 
     #[inline]
     pub fn caster(&self) -> ClientId {
-        self.0
+        self.caster
+    }
+
+    // generated by the caster, matches the ball it spawned by prediction
+    #[inline]
+    pub fn spawn_id(&self) -> u32 {
+        self.spawn_id
     }
 }
 
-#[derive(Event, Serialize, Deserialize)]
-pub struct NetworkFire;
+// emitted by the input stream on the tick it applies to
+#[derive(Event, Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub struct NetworkFire {
+    pub spawn_id: u32,
+    // aim ray from the camera through the cursor
+    pub origin: Vec3,
+    pub direction: Vec3,
+    // 0 to 1, scales launch speed
+    pub charge: f32
+}
 
-#[derive(Event, Serialize, Deserialize)]
-pub struct NetworkForce;
+// sent to the caster when server does not spawn the ball,
+// so that the predicted one is despawned
+#[derive(Event, Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct NetworkFireRejected {
+    pub spawn_id: u32
+}
 
-#[derive(Component)]
-pub struct Cache<C: Component> {
-    pub latest: C,
-    pub second: C,
-    pub elapsed_time: f32
+// emitted by the input stream on the tick it applies to
+#[derive(Event, Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub struct NetworkForce {
+    // ball the force is applied to, mapped to server when sent
+    pub entity: Entity,
+    pub direction: Vec3,
+    pub magnitude: f32,
+    // world space point the impulse is applied at
+    pub point: Vec3
+}
+
+impl MapEntities for NetworkForce {
+    fn map_entities<M: EntityMapper>(&mut self, entity_mapper: &mut M) {
+        self.entity = entity_mapper.map_entity(self.entity);
+    }
 }
 
 pub struct GameCommonPlugin;
@@ -91,15 +149,24 @@ impl Plugin for GameCommonPlugin {
             substeps: SUBSTEP
         };
 
-        app.add_plugins((
+        app.init_resource::<FireConfig>()
+        .init_resource::<ForceConfig>()
+        .init_resource::<LastSpawnIds>()
+        .init_resource::<LastForceTicks>()
+        .add_plugins((
             RapierPhysicsPlugin::<()>::default()
             .in_fixed_schedule(),
-            NetworkRigidBodyPlugin
+            NetworkRigidBodyPlugin,
+            ClientAuthorityPlugin,
+            AuthorityPlugin,
+            RateLimitPlugin,
+            InputPlugin,
+            ClockSyncPlugin,
+            TickDilationPlugin
         ))
         .replicate::<NetworkId>()
         .replicate::<NetworkFireBall>()
-        .add_client_event::<NetworkFire>(ChannelKind::Ordered)
-        .add_client_event::<NetworkForce>(ChannelKind::Ordered);
+        .add_server_event::<NetworkFireRejected>(ChannelKind::Ordered);
     }
 }
 
@@ -120,13 +187,17 @@ pub(crate) fn generate_dynamic_ball(velocity: Vec3, angular_velocity: Vec3)
         },
         Collider::ball(BALL_RADIUS),
         Restitution::coefficient(BALL_RESTITUTION),
+        // written back by rapier so that bodies at rest can be detected
+        Sleeping::default()
     )
 }
 
-pub(crate) fn euler_to_quat(euler: Vec3) -> Quat {
-    Quat::from_euler(EulerRot::XYZ, euler.x, euler.y, euler.z)
-}
-
-pub(crate) fn quat_to_euler(quat: Quat) -> Vec3 {
-    quat.to_euler(EulerRot::XYZ).into()
+// q and -q are the same rotation, picks the one 
+// on the same hemisphere as reference so that they interpolate the short way
+pub(crate) fn align_hemisphere(rotation: Quat, reference: Quat) -> Quat {
+    if rotation.dot(reference) < 0.0 {
+        -rotation
+    } else {
+        rotation
+    }
 }
@@ -1,25 +1,376 @@
+use std::io::Cursor;
 use bevy::prelude::*;
-use bevy_replicon::prelude::*;
+use bevy_replicon::{
+    prelude::*,
+    core::{
+        ctx::{WriteCtx, RemoveCtx},
+        replicon_tick::RepliconTick,
+        replication_registry::rule_fns::RuleFns
+    },
+    client::ClientSet,
+    bincode
+};
+use bevy_rapier3d::prelude::*;
 use serde::{Serialize, Deserialize};
+use super::{
+    quantization::*,
+    delta::*,
+    client_authority::LocalAuthority
+};
 
 #[derive(Component, Serialize, Deserialize, Clone)]
 pub enum NetworkRigidBody {
+    // velocities are kept updated by server only when spawned as Some,
+    // clients use them for extrapolation
     ServerSimulation {
+        tick: u32,
         translation: Vec3,
-        euler: Vec3
+        rotation: Quat,
+        velocity: Option<Vec3>,
+        angular_velocity: Option<Vec3>
     },
     ClientPrediction {
+        tick: u32,
+        translation: Vec3,
+        rotation: Quat,
+        velocity: Vec3,
+        angular_velocity: Vec3
+    },
+    // simulated by the authority holder of the ball, validated and rebroadcast by server.
+    // tick is the server fixed tick the state was accepted at
+    ClientAuthority {
+        tick: u32,
         translation: Vec3,
-        euler: Vec3,
+        rotation: Quat,
         velocity: Vec3,
         angular_velocity: Vec3
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetworkRigidBodyMode {
+    ServerSimulation,
+    ClientPrediction,
+    ClientAuthority
+}
+
+impl NetworkRigidBody {
+    #[inline]
+    pub fn mode(&self) -> NetworkRigidBodyMode {
+        match *self {
+            NetworkRigidBody::ServerSimulation { .. } => NetworkRigidBodyMode::ServerSimulation,
+            NetworkRigidBody::ClientPrediction { .. } => NetworkRigidBodyMode::ClientPrediction,
+            NetworkRigidBody::ClientAuthority { .. } => NetworkRigidBodyMode::ClientAuthority
+        }
+    }
+
+    // same state in another mode, velocities are kept
+    pub fn with_mode(&self, mode: NetworkRigidBodyMode) -> Self {
+        let tick = self.tick();
+        let translation = self.translation();
+        let rotation = self.rotation();
+        let velocity = self.velocity();
+        let angular_velocity = self.angular_velocity();
+
+        match mode {
+            NetworkRigidBodyMode::ServerSimulation => NetworkRigidBody::ServerSimulation { 
+                tick, 
+                translation, 
+                rotation, 
+                velocity: Some(velocity.unwrap_or_default()), 
+                angular_velocity: Some(angular_velocity.unwrap_or_default()) 
+            },
+            NetworkRigidBodyMode::ClientPrediction => NetworkRigidBody::ClientPrediction { 
+                tick, 
+                translation, 
+                rotation, 
+                velocity: velocity.unwrap_or_default(), 
+                angular_velocity: angular_velocity.unwrap_or_default() 
+            },
+            NetworkRigidBodyMode::ClientAuthority => NetworkRigidBody::ClientAuthority { 
+                tick, 
+                translation, 
+                rotation, 
+                velocity: velocity.unwrap_or_default(), 
+                angular_velocity: angular_velocity.unwrap_or_default() 
+            }
+        }
+    }
+
+    // server fixed tick this state was sampled at
+    #[inline]
+    pub fn tick(&self) -> u32 {
+        match *self {
+            NetworkRigidBody::ServerSimulation { tick, .. } 
+            | NetworkRigidBody::ClientPrediction { tick, .. } 
+            | NetworkRigidBody::ClientAuthority { tick, .. } => tick
+        }
+    }
+
+    #[inline]
+    pub fn translation(&self) -> Vec3 {
+        match *self {
+            NetworkRigidBody::ServerSimulation { translation, .. } 
+            | NetworkRigidBody::ClientPrediction { translation, .. } 
+            | NetworkRigidBody::ClientAuthority { translation, .. } => translation
+        }
+    }
+
+    #[inline]
+    pub fn rotation(&self) -> Quat {
+        match *self {
+            NetworkRigidBody::ServerSimulation { rotation, .. } 
+            | NetworkRigidBody::ClientPrediction { rotation, .. } 
+            | NetworkRigidBody::ClientAuthority { rotation, .. } => rotation
+        }
+    }
+
+    #[inline]
+    pub fn velocity(&self) -> Option<Vec3> {
+        match *self {
+            NetworkRigidBody::ServerSimulation { velocity, .. } => velocity,
+            NetworkRigidBody::ClientPrediction { velocity, .. } 
+            | NetworkRigidBody::ClientAuthority { velocity, .. } => Some(velocity)
+        }
+    }
+
+    #[inline]
+    pub fn angular_velocity(&self) -> Option<Vec3> {
+        match *self {
+            NetworkRigidBody::ServerSimulation { angular_velocity, .. } => angular_velocity,
+            NetworkRigidBody::ClientPrediction { angular_velocity, .. } 
+            | NetworkRigidBody::ClientAuthority { angular_velocity, .. } => Some(angular_velocity)
+        }
+    }
+
+    #[inline]
+    pub fn is_same_mode(&self, other: &NetworkRigidBody) -> bool {
+        self.mode() == other.mode()
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct FixedTick(u32);
+
+impl FixedTick {
+    #[inline]
+    pub fn get(&self) -> u32 {
+        self.0
+    }
+
+    #[inline]
+    pub fn increment(&mut self) {
+        self.0 = self.0.wrapping_add(1);
+    }
+}
+
+// server only, fixed tick the holder last interacted with the ball at
+#[derive(Component)]
+pub struct LastInteraction(u32);
+
+impl LastInteraction {
+    #[inline]
+    pub fn new(tick: u32) -> Self {
+        Self(tick)
+    }
+
+    #[inline]
+    pub fn tick(&self) -> u32 {
+        self.0
+    }
+
+    #[inline]
+    pub fn touch(&mut self, tick: u32) {
+        self.0 = tick;
+    }
+}
+
+// client only, written with every received NetworkRigidBody
+#[derive(Component, Clone, Copy)]
+pub struct NetworkRigidBodyTick {
+    server_tick: RepliconTick,
+    fixed_tick: u32
+}
+
+impl NetworkRigidBodyTick {
+    #[inline]
+    pub fn new(server_tick: RepliconTick, fixed_tick: u32) -> Self {
+        Self{
+            server_tick,
+            fixed_tick
+        }
+    }
+
+    // replicon tick of the message the state arrived with
+    #[inline]
+    pub fn server_tick(&self) -> RepliconTick {
+        self.server_tick
+    }
+
+    #[inline]
+    pub fn fixed_tick(&self) -> u32 {
+        self.fixed_tick
+    }
+
+    // server fixed ticks elapsed since the state was sampled
+    #[inline]
+    pub fn age(&self, latest: &LatestNetworkTick) -> u32 {
+        latest.fixed_tick()
+        .wrapping_sub(self.fixed_tick)
+    }
+}
+
+// client only, newest state received from server
+// and the local fixed tick it arrived at
+#[derive(Resource, Default)]
+pub struct LatestNetworkTick {
+    server_tick: RepliconTick,
+    fixed_tick: u32,
+    received_tick: u32,
+    tick_offset: Option<u32>
+}
+
+impl LatestNetworkTick {
+    #[inline]
+    pub fn server_tick(&self) -> RepliconTick {
+        self.server_tick
+    }
+
+    #[inline]
+    pub fn fixed_tick(&self) -> u32 {
+        self.fixed_tick
+    }
+
+    #[inline]
+    pub fn received_tick(&self) -> u32 {
+        self.received_tick
+    }
+
+    #[inline]
+    pub fn has_received(&self) -> bool {
+        self.tick_offset.is_some()
+    }
+
+    // local fixed tick that simulates the step following server fixed tick,
+    // established when the first state arrives
+    #[inline]
+    pub fn to_local_tick(&self, fixed_tick: u32) -> Option<u32> {
+        self.tick_offset
+        .map(|offset| fixed_tick.wrapping_add(offset))
+    }
+
+    // replaces the offset of the first state once server clock is known
+    #[inline]
+    pub(crate) fn set_tick_offset(&mut self, offset: u32) {
+        self.tick_offset = Some(offset);
+    }
+
+    // inverse of to_local_tick
+    #[inline]
+    pub fn to_server_tick(&self, local_tick: u32) -> Option<u32> {
+        self.tick_offset
+        .map(|offset| local_tick.wrapping_sub(offset))
+    }
+
+    // server fixed tick estimated from the newest state
+    #[inline]
+    pub fn estimated_fixed_tick(&self, local_tick: u32) -> u32 {
+        self.fixed_tick.wrapping_add(
+            local_tick.wrapping_sub(self.received_tick)
+        )
+    }
+}
+
 pub struct NetworkRigidBodyPlugin;
 
 impl Plugin for NetworkRigidBodyPlugin {
     fn build(&self, app: &mut App) {
-        app.replicate::<NetworkRigidBody>();
+        // server keeps NetworkRigidBody as the truth and replicates
+        // only meaningful changes of it, clients merge them back
+        app.replicate_with(RuleFns::new(
+            serialize_network_rigidbody_delta,
+            deserialize_network_rigidbody_delta
+        ))
+        .set_command_fns(
+            write_network_rigidbody, 
+            remove_network_rigidbody
+        )
+        .init_resource::<FixedTick>()
+        .init_resource::<LatestNetworkTick>()
+        .add_systems(FixedFirst, increment_fixed_tick_system)
+        .add_systems(PreUpdate, 
+            update_latest_network_tick_system
+            .after(ClientSet::Receive)
+            .run_if(client_connected)
+        );
+    }
+}
+
+fn write_network_rigidbody(
+    ctx: &mut WriteCtx,
+    rule_fns: &RuleFns<NetworkRigidBodyDelta>,
+    entity: &mut EntityMut,
+    cursor: &mut Cursor<&[u8]>
+) -> bincode::Result<()> {
+    let delta: NetworkRigidBodyDelta = rule_fns.deserialize(ctx, cursor)?;
+    let net_tick = NetworkRigidBodyTick::new(ctx.message_tick, delta.state().tick());
+
+    if let Some(mut component) = entity.get_mut::<NetworkRigidBody>() {
+        delta.merge_into(&mut component);
+    } else {
+        // first message of an entity always carries every field
+        ctx.commands.entity(entity.id())
+        .insert(delta.state().clone());
+    }
+
+    // predicted bodies follow sleeping state of server. bodies the client
+    // simulates itself are kinematic on server, which never sleeps them
+    let is_local_authority = entity.contains::<LocalAuthority>();
+    if let Some(mut sleeping) = entity.get_mut::<Sleeping>() {
+        if !is_local_authority && sleeping.sleeping != delta.is_at_rest() {
+            sleeping.sleeping = delta.is_at_rest();
+        }
+    }
+
+    if let Some(mut component) = entity.get_mut::<NetworkRigidBodyTick>() {
+        *component = net_tick;
+    } else {
+        ctx.commands.entity(entity.id())
+        .insert(net_tick);
     }
-}
\ No newline at end of file
+
+    Ok(())
+}
+
+fn remove_network_rigidbody(ctx: &mut RemoveCtx, entity: &mut EntityMut) {
+    ctx.commands.entity(entity.id())
+    .remove::<(NetworkRigidBody, NetworkRigidBodyTick)>();
+}
+
+fn increment_fixed_tick_system(mut fixed_tick: ResMut<FixedTick>) {
+    fixed_tick.increment();
+}
+
+pub(crate) fn update_latest_network_tick_system(
+    query: Query<&NetworkRigidBodyTick, Changed<NetworkRigidBodyTick>>,
+    fixed_tick: Res<FixedTick>,
+    mut latest: ResMut<LatestNetworkTick>
+) {
+    for net_tick in query.iter() {
+        if latest.tick_offset.is_some() 
+        && net_tick.server_tick() <= latest.server_tick {
+            continue;
+        }
+
+        if latest.tick_offset.is_none() {
+            latest.tick_offset = Some(
+                fixed_tick.get()
+                .wrapping_sub(net_tick.fixed_tick())
+            );
+        }
+
+        latest.server_tick = net_tick.server_tick();
+        latest.fixed_tick = net_tick.fixed_tick();
+        latest.received_tick = fixed_tick.get();
+    }
+}
@@ -0,0 +1,444 @@
+use std::collections::VecDeque;
+use bevy::{prelude::*, utils::HashMap};
+use bevy_rapier3d::{
+    prelude::*,
+    rapier::dynamics::RigidBody as RapierRigidBody
+};
+use super::{
+    *,
+    config::*,
+    network_rigidbody::*
+};
+
+#[derive(Clone, Copy)]
+pub struct PredictedState {
+    pub tick: u32,
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub velocity: Vec3,
+    pub angular_velocity: Vec3
+}
+
+impl PredictedState {
+    #[inline]
+    pub fn translation_error(&self, other: &PredictedState) -> f32 {
+        self.translation.distance_squared(other.translation)
+    }
+
+    #[inline]
+    pub fn rotation_error(&self, other: &PredictedState) -> f32 {
+        self.rotation.angle_between(other.rotation)
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct PredictedImpulse {
+    pub tick: u32,
+    pub impulse: Vec3,
+    pub torque_impulse: Vec3
+}
+
+#[derive(Component, Default)]
+pub struct PredictionHistory {
+    states: VecDeque<PredictedState>,
+    inputs: VecDeque<PredictedImpulse>,
+    error_count: u32
+}
+
+impl PredictionHistory {
+    pub fn push_state(&mut self, state: PredictedState) {
+        if let Some(back) = self.states.back() {
+            if back.tick >= state.tick {
+                self.truncate_after(state.tick.wrapping_sub(1));
+            }
+        }
+
+        self.states.push_back(state);
+        while self.states.len() > PREDICTION_HISTORY_LENGTH {
+            self.states.pop_front();
+        }
+
+        if let Some(front) = self.states.front() {
+            let oldest = front.tick;
+            self.inputs.retain(|i| i.tick >= oldest);
+        }
+    }
+
+    #[inline]
+    pub fn push_input(&mut self, input: PredictedImpulse) {
+        self.inputs.push_back(input);
+    }
+
+    #[inline]
+    pub fn state_at(&self, tick: u32) -> Option<&PredictedState> {
+        self.states.iter().find(|s| s.tick == tick)
+    }
+
+    #[inline]
+    pub fn inputs_at(&self, tick: u32) -> impl Iterator<Item = &PredictedImpulse> {
+        self.inputs.iter().filter(move |i| i.tick == tick)
+    }
+
+    #[inline]
+    pub fn truncate_after(&mut self, tick: u32) {
+        self.states.retain(|s| s.tick <= tick);
+    }
+}
+
+#[derive(Resource, Clone)]
+pub struct PredictionConfig {
+    pub smoothing_sec: f32,
+    // rendered error beyond these snaps instead of decaying
+    pub snap_translation_error: f32,
+    pub snap_rotation_error: f32
+}
+
+impl Default for PredictionConfig {
+    fn default() -> Self {
+        Self{
+            smoothing_sec: PREDICTION_SMOOTHING_SEC,
+            snap_translation_error: TRANSLATION_ERROR_THRESHOLD,
+            snap_rotation_error: ROTATION_ERROR_THRESHOLD
+        }
+    }
+}
+
+// world space offset of the rendered pose from the simulated body,
+// left by corrections and decayed over time
+#[derive(Component)]
+pub struct PredictionSmoothing {
+    translation: Vec3,
+    rotation: Quat
+}
+
+impl Default for PredictionSmoothing {
+    fn default() -> Self {
+        Self{
+            translation: Vec3::ZERO,
+            rotation: Quat::IDENTITY
+        }
+    }
+}
+
+impl PredictionSmoothing {
+    // rendered pose starts away from the body, e.g. after switching modes
+    #[inline]
+    pub fn from_offset(translation: Vec3, rotation: Quat) -> Self {
+        Self{
+            translation,
+            rotation
+        }
+    }
+
+    #[inline]
+    pub fn translation(&self) -> Vec3 {
+        self.translation
+    }
+
+    #[inline]
+    pub fn rotation(&self) -> Quat {
+        self.rotation
+    }
+
+    // keeps rendered pose where it was before the body moved 
+    pub fn correct(
+        &mut self, 
+        before: (Vec3, Quat), 
+        after: (Vec3, Quat), 
+        config: &PredictionConfig
+    ) {
+        let translation = self.translation + before.0 - after.0;
+        let rotation = (self.rotation * before.1 * after.1.inverse())
+        .normalize();
+
+        if translation.length_squared() > config.snap_translation_error
+        || rotation.angle_between(Quat::IDENTITY) > config.snap_rotation_error {
+            *self = default();
+        } else {
+            self.translation = translation;
+            self.rotation = rotation;
+        }
+    }
+}
+
+// child of a ball holding its mesh, offset by smoothing while predicted
+#[derive(Component)]
+pub struct PredictionVisual;
+
+pub struct PredictionPlugin;
+
+impl Plugin for PredictionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PredictionConfig>()
+        .add_systems(FixedUpdate,
+            reconcile_prediction_system
+            .before(BEFORE_PHYSICS_SET)
+        )
+        .add_systems(FixedUpdate,
+            record_prediction_history_system
+            .after(AFTER_PHYSICS_SET)
+        )
+        .add_systems(Update, apply_prediction_smoothing_system);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn reconcile_prediction_system(
+    mut context: ResMut<RapierContext>,
+    config: Res<RapierConfiguration>,
+    time: Res<Time>,
+    fixed_tick: Res<FixedTick>,
+    latest_tick: Res<LatestNetworkTick>,
+    // balls spawned by prediction have no NetworkRigidBody yet,
+    // but are rewound and re-simulated along with the others
+    mut query: Query<(
+        Entity,
+        Option<Ref<NetworkRigidBody>>,
+        &mut PredictionHistory
+    )>,
+    mut smoothing_query: Query<(Entity, &mut PredictionSmoothing)>,
+    prediction_config: Res<PredictionConfig>
+) {
+    let current_tick = fixed_tick.get();
+    let mut corrections = HashMap::<Entity, PredictedState>::new();
+    let mut rewind_tick: Option<u32> = None;
+
+    for (e, net_rb, mut history) in query.iter_mut() {
+        let Some(net_rb) = net_rb.filter(|n| n.is_changed()) else {
+            continue;
+        };
+
+        let NetworkRigidBody::ClientPrediction {
+            tick,
+            translation,
+            rotation,
+            velocity,
+            angular_velocity
+        } = *net_rb else {
+            continue;
+        };
+
+        let Some(local_tick) = latest_tick.to_local_tick(tick) else {
+            continue;
+        };
+        let Some(predicted) = history.state_at(local_tick) else {
+            continue;
+        };
+
+        let server_state = PredictedState{
+            tick: local_tick,
+            translation,
+            rotation,
+            velocity,
+            angular_velocity
+        };
+        let translation_error = predicted.translation_error(&server_state);
+        let rotation_error = predicted.rotation_error(&server_state);
+
+        if translation_error > TRANSLATION_ERROR_TOLERANCE
+        || rotation_error > ROTATION_ERROR_TOLERANCE {
+            history.error_count += 1;
+        } else {
+            history.error_count = 0;
+            continue;
+        }
+
+        if translation_error <= TRANSLATION_ERROR_THRESHOLD
+        && rotation_error <= ROTATION_ERROR_THRESHOLD
+        && history.error_count < PREDICTION_ERROR_COUNT_THRESHOLD {
+            continue;
+        }
+
+        info!(
+            "prediction of entity: {e:?} diverged at tick: {local_tick} translation error: {translation_error} rotation error: {rotation_error}"
+        );
+
+        history.error_count = 0;
+        corrections.insert(e, server_state);
+        rewind_tick = Some(match rewind_tick {
+            Some(t) => t.min(local_tick),
+            None => local_tick
+        });
+    }
+
+    let Some(rewind_tick) = rewind_tick else {
+        return;
+    };
+
+    let presents = smoothing_query.iter()
+    .filter_map(|(e, _)| {
+        let handle = context.entity2body().get(&e)?;
+        let body = context.bodies.get(*handle)?;
+        Some((e, get_body_pose(body)))
+    })
+    .collect::<HashMap<Entity, (Vec3, Quat)>>();
+
+    // bodies without a state at the rewind tick are not rewound,
+    // their present state is put back after re-simulation
+    let mut untouched = Vec::new();
+    for (e, _, mut history) in query.iter_mut() {
+        let Some(&handle) = context.entity2body().get(&e) else {
+            continue;
+        };
+
+        let state = match corrections.get(&e) {
+            Some(s) if s.tick == rewind_tick => Some(*s),
+            _ => history.state_at(rewind_tick).copied()
+        };
+
+        let Some(body) = context.bodies.get_mut(handle) else {
+            continue;
+        };
+        match state {
+            Some(state) => {
+                set_body_state(body, &state);
+                history.push_state(state);
+            }
+            None => {
+                untouched.push((handle, *body.position(), *body.linvel(), *body.angvel()));
+            }
+        }
+    }
+
+    let mut tick = rewind_tick.wrapping_add(1);
+    while tick != current_tick {
+        for (e, _, history) in query.iter() {
+            let Some(&handle) = context.entity2body().get(&e) else {
+                continue;
+            };
+            let Some(body) = context.bodies.get_mut(handle) else {
+                continue;
+            };
+
+            for input in history.inputs_at(tick) {
+                body.apply_impulse(input.impulse.into(), true);
+                body.apply_torque_impulse(input.torque_impulse.into(), true);
+            }
+        }
+
+        context.step_simulation(
+            config.gravity,
+            config.timestep_mode,
+            None,
+            &(),
+            &time,
+            &mut SimulationToRenderTime::default(),
+            None
+        );
+
+        for (e, _, mut history) in query.iter_mut() {
+            let Some(&handle) = context.entity2body().get(&e) else {
+                continue;
+            };
+            let Some(body) = context.bodies.get_mut(handle) else {
+                continue;
+            };
+
+            let state = match corrections.get(&e) {
+                Some(s) if s.tick == tick => {
+                    set_body_state(body, s);
+                    *s
+                }
+                _ => get_body_state(body, tick)
+            };
+            history.push_state(state);
+        }
+
+        tick = tick.wrapping_add(1);
+    }
+
+    for (handle, position, linvel, angvel) in untouched {
+        if let Some(body) = context.bodies.get_mut(handle) {
+            body.set_position(position, true);
+            body.set_linvel(linvel, true);
+            body.set_angvel(angvel, true);
+        }
+    }
+
+    context.propagate_modified_body_positions_to_colliders();
+
+    for (e, mut smoothing) in smoothing_query.iter_mut() {
+        let Some(&before) = presents.get(&e) else {
+            continue;
+        };
+        let Some(&handle) = context.entity2body().get(&e) else {
+            continue;
+        };
+        let Some(body) = context.bodies.get(handle) else {
+            continue;
+        };
+
+        smoothing.correct(before, get_body_pose(body), &prediction_config);
+    }
+}
+
+fn record_prediction_history_system(
+    mut query: Query<(
+        &Transform,
+        &Velocity,
+        &mut PredictionHistory
+    )>,
+    fixed_tick: Res<FixedTick>
+) {
+    let tick = fixed_tick.get();
+    for (transform, velocity, mut history) in query.iter_mut() {
+        history.push_state(PredictedState{
+            tick,
+            translation: transform.translation,
+            rotation: transform.rotation,
+            velocity: velocity.linvel,
+            angular_velocity: velocity.angvel
+        });
+    }
+}
+
+fn apply_prediction_smoothing_system(
+    mut parents: Query<(&Transform, &mut PredictionSmoothing, &Children)>,
+    mut visuals: Query<&mut Transform, (With<PredictionVisual>, Without<PredictionSmoothing>)>,
+    config: Res<PredictionConfig>,
+    time: Res<Time>
+) {
+    let decay = if config.smoothing_sec > 0.0 {
+        (-time.delta_seconds() / config.smoothing_sec).exp()
+    } else {
+        0.0
+    };
+
+    for (transform, mut smoothing, children) in parents.iter_mut() {
+        smoothing.translation *= decay;
+        smoothing.rotation = Quat::IDENTITY.slerp(smoothing.rotation, decay);
+
+        // world space offset into the space of the body
+        let inverse = transform.rotation.inverse();
+        for &child in children.iter() {
+            if let Ok(mut visual) = visuals.get_mut(child) {
+                visual.translation = inverse * smoothing.translation;
+                visual.rotation = inverse * smoothing.rotation * transform.rotation;
+            }
+        }
+    }
+}
+
+fn set_body_state(body: &mut RapierRigidBody, state: &PredictedState) {
+    body.set_translation(state.translation.into(), true);
+    body.set_rotation(state.rotation.into(), true);
+    body.set_linvel(state.velocity.into(), true);
+    body.set_angvel(state.angular_velocity.into(), true);
+}
+
+fn get_body_state(body: &RapierRigidBody, tick: u32) -> PredictedState {
+    PredictedState{
+        tick,
+        translation: (*body.translation()).into(),
+        rotation: (*body.rotation()).into(),
+        velocity: (*body.linvel()).into(),
+        angular_velocity: (*body.angvel()).into()
+    }
+}
+
+fn get_body_pose(body: &RapierRigidBody) -> (Vec3, Quat) {
+    (
+        (*body.translation()).into(),
+        (*body.rotation()).into()
+    )
+}
@@ -0,0 +1,164 @@
+use bevy::{
+    prelude::*,
+    utils::HashMap
+};
+use bevy_replicon::{
+    prelude::*,
+    server::server_tick::ServerTick
+};
+use super::{
+    *,
+    config::*,
+    delta::*,
+    quantization::*,
+    authority::*
+};
+
+// server only, priority of a pending change per client.
+// accumulated every network tick until sent, so bodies waiting longer
+// eventually win over closer or faster ones
+#[derive(Component, Default)]
+pub struct NetworkPriority {
+    accumulated: HashMap<ClientId, f32>
+}
+
+impl NetworkPriority {
+    #[inline]
+    pub fn get(&self, client_id: ClientId) -> f32 {
+        self.accumulated.get(&client_id)
+        .copied()
+        .unwrap_or_default()
+    }
+}
+
+#[derive(Resource)]
+pub struct PriorityConfig {
+    // bytes of rigidbody updates per client per network tick.
+    // a body is published to all its receivers at once, so the budgets
+    // are not independent, see publish_network_rigidbody_delta_system
+    pub budget_bytes: usize,
+    pub distance_scale: f32,
+    pub velocity_weight: f32,
+    pub owner_weight: f32,
+    pub entity_overhead: usize
+}
+
+impl Default for PriorityConfig {
+    fn default() -> Self {
+        Self{
+            budget_bytes: DEV_MAX_UPDATE_SNAPSHOT_SIZE,
+            distance_scale: PRIORITY_DISTANCE_SCALE,
+            velocity_weight: PRIORITY_VELOCITY_WEIGHT,
+            owner_weight: PRIORITY_OWNER_WEIGHT,
+            entity_overhead: PRIORITY_ENTITY_OVERHEAD
+        }
+    }
+}
+
+impl PriorityConfig {
+    // priority gained per network tick
+    pub fn weight(&self, distance: f32, speed: f32, is_owner: bool) -> f32 {
+        let mut weight = (1.0 + speed * self.velocity_weight) 
+        / (1.0 + distance / self.distance_scale);
+        if is_owner {
+            weight *= self.owner_weight;
+        }
+        weight
+    }
+}
+
+pub struct PriorityPlugin;
+
+impl Plugin for PriorityPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PriorityConfig>()
+        .add_systems(PostUpdate, 
+            publish_network_rigidbody_delta_system
+            .after(ServerPlugin::increment_tick)
+            .after(update_network_rigidbody_delta_mask_system)
+            .before(ServerSet::Send)
+            .run_if(resource_changed::<ServerTick>)
+        );
+    }
+}
+
+// pending changes are published in order of priority as long as
+// they fit in the budget of every client receiving them,
+// the rest keeps accumulating for the next network tick.
+// a change is one replicated component sent to every client it is
+// visible to, so a body is held back for all of them when any one
+// is out of budget. hiding it per client would despawn it there
+#[allow(clippy::type_complexity)]
+fn publish_network_rigidbody_delta_system(
+    mut query: Query<(
+        Entity,
+        &GlobalTransform,
+        Option<&NetworkAuthority>,
+        &mut NetworkRigidBodyDelta,
+        &mut NetworkPriority
+    )>,
+    viewpoints: Query<(&NetworkId, &GlobalTransform)>,
+    connected_clients: Res<ConnectedClients>,
+    config: Res<PriorityConfig>
+) {
+    let viewpoints = viewpoints.iter()
+    .map(|(net_id, transform)| (net_id.client_id(), transform.translation()))
+    .collect::<HashMap<_, _>>();
+    let mut budgets = connected_clients.iter()
+    .map(|client| (client.id(), config.budget_bytes))
+    .collect::<HashMap<_, _>>();
+
+    let mut candidates = Vec::new();
+    for (e, transform, authority, delta, mut priority) in query.iter_mut() {
+        priority.accumulated
+        .retain(|client_id, _| budgets.contains_key(client_id));
+        if !delta.is_pending() {
+            continue;
+        }
+
+        let speed = delta.state()
+        .velocity()
+        .unwrap_or_default()
+        .length();
+        let mut highest = 0.0f32;
+        for client in connected_clients.iter() {
+            if !client.visibility().is_visible(e) {
+                continue;
+            }
+
+            let distance = viewpoints.get(&client.id())
+            .map_or(0.0, |v| v.distance(transform.translation()));
+            let is_owner = authority.is_some_and(|a| a.is_held_by(client.id()));
+            let accumulated = priority.accumulated
+            .entry(client.id())
+            .or_default();
+            *accumulated += config.weight(distance, speed, is_owner);
+            highest = highest.max(*accumulated);
+        }
+        candidates.push((e, highest));
+    }
+    candidates.sort_unstable_by(|a, b| b.1.total_cmp(&a.1));
+
+    for (e, _) in candidates {
+        let Ok((_, _, _, mut delta, mut priority)) = query.get_mut(e) else {
+            continue;
+        };
+
+        let size = network_rigidbody_delta_size(&delta) + config.entity_overhead;
+        let receivers = connected_clients.iter()
+        .filter(|client| client.visibility().is_visible(e))
+        .map(|client| client.id())
+        .collect::<Vec<_>>();
+        if receivers.iter().any(|client_id| budgets[client_id] < size) {
+            continue;
+        }
+
+        for client_id in receivers {
+            if let Some(budget) = budgets.get_mut(&client_id) {
+                *budget -= size;
+            }
+            priority.accumulated.remove(&client_id);
+        }
+        delta.publish();
+    }
+}
@@ -0,0 +1,516 @@
+use std::io::{Cursor, Read, Write};
+use bevy::prelude::*;
+use bevy_replicon::{
+    core::ctx::{SerializeCtx, WriteCtx},
+    bincode::{self, DefaultOptions, Options}
+};
+use super::{
+    config::*,
+    network_rigidbody::*,
+    delta::*
+};
+
+const FLAG_CLIENT_SIMULATED: u8 = 1 << 0;
+// client simulated bodies always carry velocities,
+// so the presence bits are reused for their mode
+const FLAG_CLIENT_AUTHORITY: u8 = 1 << 1;
+const FLAG_VELOCITY: u8 = 1 << 1;
+const FLAG_ANGULAR_VELOCITY: u8 = 1 << 2;
+// change mask of NetworkRigidBodyDelta occupies the upper bits
+const CHANGE_MASK_SHIFT: u8 = 3;
+const FLAG_AT_REST: u8 = 1 << 7;
+
+// components other than the largest one are within this range
+const SMALLEST_THREE_RANGE: f32 = std::f32::consts::FRAC_1_SQRT_2;
+const SMALLEST_THREE_BITS: u32 = 10;
+const SMALLEST_THREE_MAX: u32 = (1 << SMALLEST_THREE_BITS) - 1;
+
+// each axis is mapped to u16 over the network world bounds,
+// outside of the bounds is clamped
+pub fn quantize_translation(translation: Vec3) -> [u16; 3] {
+    let size = NET_WORLD_BOUNDS_MAX - NET_WORLD_BOUNDS_MIN;
+    let normalized = ((translation - NET_WORLD_BOUNDS_MIN) / size)
+    .clamp(Vec3::ZERO, Vec3::ONE);
+    let q = (normalized * u16::MAX as f32).round();
+    [q.x as u16, q.y as u16, q.z as u16]
+}
+
+pub fn dequantize_translation(quantized: [u16; 3]) -> Vec3 {
+    let size = NET_WORLD_BOUNDS_MAX - NET_WORLD_BOUNDS_MIN;
+    let normalized = Vec3::new(
+        quantized[0] as f32,
+        quantized[1] as f32,
+        quantized[2] as f32
+    ) / u16::MAX as f32;
+    NET_WORLD_BOUNDS_MIN + normalized * size
+}
+
+// smallest three: 2 bits for index of the largest component,
+// 10 bits for each of the others
+pub fn compress_rotation(rotation: Quat) -> u32 {
+    let mut components = rotation.normalize().to_array();
+    let largest = components.iter()
+    .enumerate()
+    .max_by(|(_, a), (_, b)| a.abs().total_cmp(&b.abs()))
+    .map(|(i, _)| i)
+    .unwrap_or(3);
+
+    // q and -q are the same rotation, keep the dropped one positive
+    if components[largest] < 0.0 {
+        components.iter_mut().for_each(|c| *c = -*c);
+    }
+
+    let mut packed = largest as u32;
+    for (i, c) in components.iter().enumerate() {
+        if i == largest {
+            continue;
+        }
+        let normalized = (c / SMALLEST_THREE_RANGE * 0.5 + 0.5).clamp(0.0, 1.0);
+        let q = (normalized * SMALLEST_THREE_MAX as f32).round() as u32;
+        packed = (packed << SMALLEST_THREE_BITS) | q;
+    }
+    packed
+}
+
+pub fn decompress_rotation(packed: u32) -> Quat {
+    let largest = (packed >> (SMALLEST_THREE_BITS * 3)) as usize & 0b11;
+    let mut components = [0.0f32; 4];
+    let mut shift = SMALLEST_THREE_BITS * 3;
+    let mut sum = 0.0;
+    for (i, c) in components.iter_mut().enumerate() {
+        if i == largest {
+            continue;
+        }
+        shift -= SMALLEST_THREE_BITS;
+        let q = (packed >> shift) & SMALLEST_THREE_MAX;
+        *c = (q as f32 / SMALLEST_THREE_MAX as f32 - 0.5) * 2.0 * SMALLEST_THREE_RANGE;
+        sum += *c * *c;
+    }
+    components[largest] = (1.0 - sum).max(0.0).sqrt();
+
+    Quat::from_array(components).normalize()
+}
+
+// each axis is mapped to i16 in steps of precision
+pub fn quantize_velocity(velocity: Vec3, precision: f32) -> [i16; 3] {
+    let q = (velocity / precision)
+    .round()
+    .clamp(Vec3::splat(i16::MIN as f32), Vec3::splat(i16::MAX as f32));
+    [q.x as i16, q.y as i16, q.z as i16]
+}
+
+pub fn dequantize_velocity(quantized: [i16; 3], precision: f32) -> Vec3 {
+    Vec3::new(
+        quantized[0] as f32,
+        quantized[1] as f32,
+        quantized[2] as f32
+    ) * precision
+}
+
+pub fn serialize_network_rigidbody_delta(
+    _ctx: &SerializeCtx,
+    delta: &NetworkRigidBodyDelta,
+    cursor: &mut Cursor<Vec<u8>>
+) -> bincode::Result<()> {
+    write_network_rigidbody_delta(delta, cursor)
+}
+
+pub fn deserialize_network_rigidbody_delta(
+    _ctx: &mut WriteCtx,
+    cursor: &mut Cursor<&[u8]>
+) -> bincode::Result<NetworkRigidBodyDelta> {
+    read_network_rigidbody_delta(cursor)
+}
+
+// flags byte carries mode, presence of velocities and change mask,
+// followed by tick and the changed fields only
+pub fn write_network_rigidbody_delta<W: Write>(
+    delta: &NetworkRigidBodyDelta,
+    writer: &mut W
+) -> bincode::Result<()> {
+    let net_rb = delta.state();
+    let mask = delta.mask() & DELTA_ALL;
+    let mut flags = mask << CHANGE_MASK_SHIFT;
+    match *net_rb {
+        NetworkRigidBody::ServerSimulation { velocity, angular_velocity, .. } => {
+            if velocity.is_some() {
+                flags |= FLAG_VELOCITY;
+            }
+            if angular_velocity.is_some() {
+                flags |= FLAG_ANGULAR_VELOCITY;
+            }
+        }
+        NetworkRigidBody::ClientPrediction { .. } => {
+            flags |= FLAG_CLIENT_SIMULATED;
+        }
+        NetworkRigidBody::ClientAuthority { .. } => {
+            flags |= FLAG_CLIENT_SIMULATED | FLAG_CLIENT_AUTHORITY;
+        }
+    }
+    if delta.is_at_rest() {
+        flags |= FLAG_AT_REST;
+    }
+
+    writer.write_all(&[flags])?;
+    DefaultOptions::new().serialize_into(&mut *writer, &net_rb.tick())?;
+    if mask & DELTA_TRANSLATION != 0 {
+        for q in quantize_translation(net_rb.translation()) {
+            writer.write_all(&q.to_le_bytes())?;
+        }
+    }
+    if mask & DELTA_ROTATION != 0 {
+        writer.write_all(&compress_rotation(net_rb.rotation()).to_le_bytes())?;
+    }
+    if mask & DELTA_VELOCITY != 0 {
+        if let Some(velocity) = net_rb.velocity() {
+            for q in quantize_velocity(velocity, NET_VELOCITY_PRECISION) {
+                writer.write_all(&q.to_le_bytes())?;
+            }
+        }
+    }
+    if mask & DELTA_ANGULAR_VELOCITY != 0 {
+        if let Some(angular_velocity) = net_rb.angular_velocity() {
+            for q in quantize_velocity(angular_velocity, NET_ANGULAR_VELOCITY_PRECISION) {
+                writer.write_all(&q.to_le_bytes())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// bytes written by write_network_rigidbody_delta
+pub fn network_rigidbody_delta_size(delta: &NetworkRigidBodyDelta) -> usize {
+    let net_rb = delta.state();
+    let mask = delta.mask();
+    let tick_size = DefaultOptions::new()
+    .serialized_size(&net_rb.tick())
+    .unwrap_or(5) as usize;
+
+    let mut size = 1 + tick_size;
+    if mask & DELTA_TRANSLATION != 0 {
+        size += 6;
+    }
+    if mask & DELTA_ROTATION != 0 {
+        size += 4;
+    }
+    if mask & DELTA_VELOCITY != 0 && net_rb.velocity().is_some() {
+        size += 6;
+    }
+    if mask & DELTA_ANGULAR_VELOCITY != 0 && net_rb.angular_velocity().is_some() {
+        size += 6;
+    }
+    size
+}
+
+// fields out of the change mask are left as default
+pub fn read_network_rigidbody_delta<R: Read>(reader: &mut R)
+-> bincode::Result<NetworkRigidBodyDelta> {
+    let mut flags = [0u8; 1];
+    reader.read_exact(&mut flags)?;
+    let flags = flags[0];
+    let mask = (flags >> CHANGE_MASK_SHIFT) & DELTA_ALL;
+    let client_simulated = flags & FLAG_CLIENT_SIMULATED != 0;
+
+    let tick: u32 = DefaultOptions::new().deserialize_from(&mut *reader)?;
+    let translation = if mask & DELTA_TRANSLATION != 0 {
+        dequantize_translation([
+            read_u16(reader)?,
+            read_u16(reader)?,
+            read_u16(reader)?
+        ])
+    } else {
+        Vec3::ZERO
+    };
+    let rotation = if mask & DELTA_ROTATION != 0 {
+        let mut rotation = [0u8; 4];
+        reader.read_exact(&mut rotation)?;
+        decompress_rotation(u32::from_le_bytes(rotation))
+    } else {
+        Quat::IDENTITY
+    };
+
+    let velocity = if !client_simulated && flags & FLAG_VELOCITY == 0 {
+        None
+    } else if mask & DELTA_VELOCITY != 0 {
+        Some(dequantize_velocity(
+            [read_i16(reader)?, read_i16(reader)?, read_i16(reader)?],
+            NET_VELOCITY_PRECISION
+        ))
+    } else {
+        Some(Vec3::ZERO)
+    };
+    let angular_velocity = if !client_simulated && flags & FLAG_ANGULAR_VELOCITY == 0 {
+        None
+    } else if mask & DELTA_ANGULAR_VELOCITY != 0 {
+        Some(dequantize_velocity(
+            [read_i16(reader)?, read_i16(reader)?, read_i16(reader)?],
+            NET_ANGULAR_VELOCITY_PRECISION
+        ))
+    } else {
+        Some(Vec3::ZERO)
+    };
+
+    let net_rb = if client_simulated && flags & FLAG_CLIENT_AUTHORITY != 0 {
+        NetworkRigidBody::ClientAuthority {
+            tick,
+            translation,
+            rotation,
+            velocity: velocity.unwrap_or_default(),
+            angular_velocity: angular_velocity.unwrap_or_default()
+        }
+    } else if client_simulated {
+        NetworkRigidBody::ClientPrediction {
+            tick,
+            translation,
+            rotation,
+            velocity: velocity.unwrap_or_default(),
+            angular_velocity: angular_velocity.unwrap_or_default()
+        }
+    } else {
+        NetworkRigidBody::ServerSimulation {
+            tick,
+            translation,
+            rotation,
+            velocity,
+            angular_velocity
+        }
+    };
+    Ok(NetworkRigidBodyDelta::from_parts(
+        net_rb, 
+        mask, 
+        flags & FLAG_AT_REST != 0
+    ))
+}
+
+#[inline]
+fn read_u16<R: Read>(reader: &mut R) -> bincode::Result<u16> {
+    let mut bytes = [0u8; 2];
+    reader.read_exact(&mut bytes)?;
+    Ok(u16::from_le_bytes(bytes))
+}
+
+#[inline]
+fn read_i16<R: Read>(reader: &mut R) -> bincode::Result<i16> {
+    let mut bytes = [0u8; 2];
+    reader.read_exact(&mut bytes)?;
+    Ok(i16::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    fn translation_step() -> Vec3 {
+        (NET_WORLD_BOUNDS_MAX - NET_WORLD_BOUNDS_MIN) / u16::MAX as f32
+    }
+
+    #[test]
+    fn translation_round_trip() {
+        let max_error = translation_step() * 0.5 + Vec3::splat(1e-4);
+        for translation in [
+            Vec3::ZERO,
+            BALL_SPAWN_POSITION,
+            Vec3::new(-12.345, 0.5, 29.99),
+            Vec3::new(NET_WORLD_BOUNDS_MIN.x, DROPPED_Y, NET_WORLD_BOUNDS_MAX.z)
+        ] {
+            let restored = dequantize_translation(quantize_translation(translation));
+            let error = (restored - translation).abs();
+            assert!(error.cmple(max_error).all(), "{translation} -> {restored}");
+        }
+    }
+
+    #[test]
+    fn translation_out_of_bounds_is_clamped() {
+        let restored = dequantize_translation(
+            quantize_translation(NET_WORLD_BOUNDS_MAX + Vec3::ONE)
+        );
+        assert!((restored - NET_WORLD_BOUNDS_MAX).abs().max_element() < 1e-3);
+    }
+
+    #[test]
+    fn rotation_round_trip() {
+        for rotation in [
+            Quat::IDENTITY,
+            Quat::from_euler(EulerRot::XYZ, 0.1, 0.2, 0.3),
+            Quat::from_euler(EulerRot::XYZ, -3.0, 1.5, 2.9),
+            Quat::from_rotation_y(std::f32::consts::PI),
+            Quat::from_axis_angle(Vec3::ONE.normalize(), -2.0)
+        ] {
+            for q in [rotation, -rotation] {
+                let restored = decompress_rotation(compress_rotation(q));
+                let error = restored.angle_between(rotation);
+                assert!(error < 0.005, "{rotation} error: {error}");
+            }
+        }
+    }
+
+    #[test]
+    fn velocity_round_trip() {
+        let max_error = NET_VELOCITY_PRECISION * 0.5 + 1e-4;
+        for velocity in [
+            Vec3::ZERO,
+            INITIAL_VELOCITY,
+            Vec3::new(-BASE_SPEED, 3.3333, 0.004)
+        ] {
+            let restored = dequantize_velocity(
+                quantize_velocity(velocity, NET_VELOCITY_PRECISION),
+                NET_VELOCITY_PRECISION
+            );
+            assert!((restored - velocity).abs().max_element() <= max_error);
+        }
+    }
+
+    #[test]
+    fn network_rigidbody_round_trip() {
+        let net_rb = NetworkRigidBody::ClientPrediction {
+            tick: 123456,
+            translation: Vec3::new(1.0, 2.0, 3.0),
+            rotation: Quat::from_euler(EulerRot::XYZ, 0.5, -0.25, 1.0),
+            velocity: Vec3::new(0.0, -9.81, 2.5),
+            angular_velocity: INITIAL_ANGULAR_VELOCITY
+        };
+
+        let mut bytes = Vec::new();
+        write_network_rigidbody_delta(&NetworkRigidBodyDelta::new(net_rb), &mut bytes).unwrap();
+        assert!(bytes.len() <= DEV_MAX_SNAPSHOT_SIZE);
+
+        let restored = read_network_rigidbody_delta(&mut bytes.as_slice()).unwrap();
+        assert_eq!(restored.mask(), DELTA_ALL);
+        let NetworkRigidBody::ClientPrediction {
+            tick,
+            translation,
+            rotation,
+            velocity,
+            angular_velocity
+        } = *restored.state() else {
+            panic!("should be client predicted RB");
+        };
+        assert_eq!(tick, 123456);
+        assert!((translation - Vec3::new(1.0, 2.0, 3.0)).length() < 0.01);
+        assert!(rotation.angle_between(Quat::from_euler(EulerRot::XYZ, 0.5, -0.25, 1.0)) < 0.005);
+        assert!((velocity - Vec3::new(0.0, -9.81, 2.5)).length() < 0.01);
+        assert!((angular_velocity - INITIAL_ANGULAR_VELOCITY).length() < 0.01);
+    }
+
+    #[test]
+    fn server_simulation_without_velocity_round_trip() {
+        let net_rb = NetworkRigidBody::ServerSimulation {
+            tick: 7,
+            translation: BALL_SPAWN_POSITION,
+            rotation: BALL_SPAWN_ROTATION,
+            velocity: None,
+            angular_velocity: None
+        };
+
+        let mut bytes = Vec::new();
+        write_network_rigidbody_delta(&NetworkRigidBodyDelta::new(net_rb), &mut bytes).unwrap();
+        let restored = read_network_rigidbody_delta(&mut bytes.as_slice()).unwrap();
+        assert!(matches!(
+            *restored.state(),
+            NetworkRigidBody::ServerSimulation {
+                tick: 7,
+                velocity: None,
+                angular_velocity: None,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn partial_delta_merges_changed_fields_only() {
+        let baseline = NetworkRigidBody::ClientPrediction {
+            tick: 10,
+            translation: BALL_SPAWN_POSITION,
+            rotation: BALL_SPAWN_ROTATION,
+            velocity: INITIAL_VELOCITY,
+            angular_velocity: INITIAL_ANGULAR_VELOCITY
+        };
+        let current = NetworkRigidBody::ClientPrediction {
+            tick: 11,
+            translation: BALL_SPAWN_POSITION + Vec3::Y,
+            rotation: Quat::from_rotation_y(1.0),
+            velocity: Vec3::ZERO,
+            angular_velocity: Vec3::ZERO
+        };
+
+        let mut bytes = Vec::new();
+        write_network_rigidbody_delta(
+            &NetworkRigidBodyDelta::from_parts(current, DELTA_TRANSLATION, false),
+            &mut bytes
+        ).unwrap();
+        let mut full = Vec::new();
+        write_network_rigidbody_delta(&NetworkRigidBodyDelta::new(baseline.clone()), &mut full).unwrap();
+        assert!(bytes.len() < full.len());
+
+        let delta = read_network_rigidbody_delta(&mut bytes.as_slice()).unwrap();
+        let mut merged = baseline;
+        delta.merge_into(&mut merged);
+        assert_eq!(merged.tick(), 11);
+        assert!((merged.translation() - (BALL_SPAWN_POSITION + Vec3::Y)).length() < 0.01);
+        assert_eq!(merged.rotation(), BALL_SPAWN_ROTATION);
+        assert_eq!(merged.velocity(), Some(INITIAL_VELOCITY));
+        assert_eq!(merged.angular_velocity(), Some(INITIAL_ANGULAR_VELOCITY));
+    }
+
+    #[test]
+    fn at_rest_without_changes_round_trip() {
+        let net_rb = NetworkRigidBody::ServerSimulation {
+            tick: 42,
+            translation: BALL_SPAWN_POSITION,
+            rotation: BALL_SPAWN_ROTATION,
+            velocity: Some(Vec3::ZERO),
+            angular_velocity: None
+        };
+
+        let mut bytes = Vec::new();
+        write_network_rigidbody_delta(
+            &NetworkRigidBodyDelta::from_parts(net_rb, 0, true),
+            &mut bytes
+        ).unwrap();
+        assert_eq!(bytes.len(), 2);
+
+        let delta = read_network_rigidbody_delta(&mut bytes.as_slice()).unwrap();
+        assert!(delta.is_at_rest());
+        assert_eq!(delta.mask(), 0);
+        assert_eq!(delta.state().tick(), 42);
+    }
+
+    #[test]
+    fn delta_size_matches_written_bytes() {
+        let net_rb = NetworkRigidBody::ServerSimulation {
+            tick: 70000,
+            translation: BALL_SPAWN_POSITION,
+            rotation: BALL_SPAWN_ROTATION,
+            velocity: Some(INITIAL_VELOCITY),
+            angular_velocity: None
+        };
+
+        for mask in 0..=DELTA_ALL {
+            let delta = NetworkRigidBodyDelta::from_parts(net_rb.clone(), mask, false);
+            let mut bytes = Vec::new();
+            write_network_rigidbody_delta(&delta, &mut bytes).unwrap();
+            assert_eq!(network_rigidbody_delta_size(&delta), bytes.len(), "mask: {mask}");
+        }
+    }
+
+    #[test]
+    fn client_authority_round_trip() {
+        let net_rb = NetworkRigidBody::ClientAuthority {
+            tick: 300,
+            translation: BALL_SPAWN_POSITION,
+            rotation: BALL_SPAWN_ROTATION,
+            velocity: INITIAL_VELOCITY,
+            angular_velocity: Vec3::ZERO
+        };
+
+        let mut bytes = Vec::new();
+        write_network_rigidbody_delta(&NetworkRigidBodyDelta::new(net_rb), &mut bytes).unwrap();
+        let restored = read_network_rigidbody_delta(&mut bytes.as_slice()).unwrap();
+        assert!(matches!(
+            *restored.state(),
+            NetworkRigidBody::ClientAuthority { tick: 300, .. }
+        ));
+        assert_eq!(restored.state().velocity(), Some(INITIAL_VELOCITY));
+    }
+}
@@ -0,0 +1,307 @@
+use std::collections::VecDeque;
+use bevy::{
+    prelude::*,
+    ecs::system::SystemParam,
+    utils::HashMap
+};
+use bevy_replicon::prelude::*;
+use bevy_replicon_renet::renet::{self, RenetServer};
+use super::{
+    *,
+    config::*,
+    network_rigidbody::*
+};
+
+// client events limited separately, each by its own bucket
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum RateLimitedEvent {
+    Fire,
+    Force,
+    AuthorityRequest,
+    Input,
+    AuthorityState,
+    Ping
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Violation {
+    RateLimited(RateLimitedEvent),
+    TooManyFireBalls
+}
+
+// what server does with events of a client over its limits
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AbusePolicy {
+    // applied anyway, only logged
+    Log,
+    Drop,
+    // dropped, and the client is disconnected after too many violations
+    Kick
+}
+
+// sent on every violation so that games can add their own handling
+#[derive(Event, Clone, Copy, Debug)]
+pub struct ClientViolation {
+    pub client_id: ClientId,
+    pub violation: Violation,
+    // violations of the client within the window, this one included
+    pub count: u32
+}
+
+#[derive(Clone, Copy)]
+pub struct TokenBucketConfig {
+    // events that can be sent at once
+    pub burst: f32,
+    pub per_sec: f32
+}
+
+impl TokenBucketConfig {
+    #[inline]
+    pub fn new(burst: f32, per_sec: f32) -> Self {
+        Self{ burst, per_sec }
+    }
+}
+
+#[derive(Resource, Clone)]
+pub struct RateLimitConfig {
+    pub buckets: HashMap<RateLimitedEvent, TokenBucketConfig>,
+    pub max_live_fire_balls: usize,
+    pub policy: AbusePolicy,
+    pub kick_violations: u32,
+    pub violation_window_ticks: u32
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self{
+            buckets: HashMap::from([
+                (RateLimitedEvent::Fire, TokenBucketConfig::new(
+                    RATE_LIMIT_FIRE_BURST,
+                    RATE_LIMIT_FIRE_PER_SEC
+                )),
+                (RateLimitedEvent::Force, TokenBucketConfig::new(
+                    RATE_LIMIT_FORCE_BURST,
+                    RATE_LIMIT_FORCE_PER_SEC
+                )),
+                (RateLimitedEvent::AuthorityRequest, TokenBucketConfig::new(
+                    RATE_LIMIT_AUTHORITY_REQUEST_BURST,
+                    RATE_LIMIT_AUTHORITY_REQUEST_PER_SEC
+                )),
+                (RateLimitedEvent::Input, TokenBucketConfig::new(
+                    RATE_LIMIT_INPUT_BURST,
+                    RATE_LIMIT_INPUT_PER_SEC
+                )),
+                (RateLimitedEvent::AuthorityState, TokenBucketConfig::new(
+                    RATE_LIMIT_AUTHORITY_STATE_BURST,
+                    RATE_LIMIT_AUTHORITY_STATE_PER_SEC
+                )),
+                (RateLimitedEvent::Ping, TokenBucketConfig::new(
+                    RATE_LIMIT_PING_BURST,
+                    RATE_LIMIT_PING_PER_SEC
+                ))
+            ]),
+            max_live_fire_balls: MAX_LIVE_FIRE_BALLS_PER_CLIENT,
+            policy: AbusePolicy::Drop,
+            kick_violations: ABUSE_KICK_VIOLATIONS,
+            violation_window_ticks: (ABUSE_VIOLATION_WINDOW_SEC * PHYSICS_FIXED_TICK_RATE) as u32
+        }
+    }
+}
+
+// refilled by fixed ticks, starts full
+#[derive(Default)]
+pub struct TokenBucket {
+    tokens: f32,
+    last_tick: Option<u32>
+}
+
+impl TokenBucket {
+    pub fn try_take(&mut self, tick: u32, config: &TokenBucketConfig) -> bool {
+        let refill = match self.last_tick {
+            Some(last) => tick.wrapping_sub(last) as f32
+            * config.per_sec / PHYSICS_FIXED_TICK_RATE,
+            None => config.burst
+        };
+        self.tokens = (self.tokens + refill).min(config.burst);
+        self.last_tick = Some(tick);
+
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+#[derive(Default)]
+struct ClientLimitState {
+    buckets: HashMap<RateLimitedEvent, TokenBucket>,
+    // violations counted by fixed tick, oldest first
+    violations: VecDeque<(u32, u32)>,
+    logged_tick: Option<u32>
+}
+
+impl ClientLimitState {
+    // violations within the window ending at tick, this one included
+    fn add_violation(&mut self, tick: u32, window_ticks: u32) -> u32 {
+        while self.violations.front()
+        .is_some_and(|(t, _)| tick.wrapping_sub(*t) >= window_ticks) {
+            self.violations.pop_front();
+        }
+        match self.violations.back_mut() {
+            Some((t, count)) if *t == tick => *count += 1,
+            _ => self.violations.push_back((tick, 1))
+        }
+        self.violations.iter().map(|(_, count)| count).sum()
+    }
+
+    // violations are logged once per window
+    fn should_log(&mut self, tick: u32, window_ticks: u32) -> bool {
+        if self.logged_tick.is_some_and(|t| tick.wrapping_sub(t) < window_ticks) {
+            return false;
+        }
+        self.logged_tick = Some(tick);
+        true
+    }
+}
+
+// server only
+#[derive(Resource, Default)]
+pub struct RateLimiter(HashMap<ClientId, ClientLimitState>);
+
+impl RateLimiter {
+    #[inline]
+    pub fn remove(&mut self, client_id: ClientId) {
+        self.0.remove(&client_id);
+    }
+}
+
+// server only, checks client events against the limits of the client
+// and reports violations
+#[derive(SystemParam)]
+pub struct ClientLimits<'w, 's> {
+    limiter: ResMut<'w, RateLimiter>,
+    violations: EventWriter<'w, ClientViolation>,
+    fire_balls: Query<'w, 's, &'static NetworkFireBall>,
+    fixed_tick: Res<'w, FixedTick>,
+    config: Res<'w, RateLimitConfig>
+}
+
+impl ClientLimits<'_, '_> {
+    // false when the event should be dropped
+    pub fn allow(&mut self, client_id: ClientId, event: RateLimitedEvent) -> bool {
+        let Some(bucket_config) = self.config.buckets.get(&event) else {
+            return true;
+        };
+        let tick = self.fixed_tick.get();
+        let is_allowed = self.limiter.0.entry(client_id)
+        .or_default()
+        .buckets.entry(event)
+        .or_default()
+        .try_take(tick, bucket_config);
+
+        is_allowed || self.report(client_id, Violation::RateLimited(event))
+    }
+
+    // live entities are balls spawned before this run, accepted
+    // are the ones whose spawn commands have not been applied yet
+    pub fn allow_fire_ball(&mut self, client_id: ClientId, accepted: usize) -> bool {
+        let live = self.fire_balls.iter()
+        .filter(|b| b.caster() == client_id)
+        .count();
+
+        live + accepted < self.config.max_live_fire_balls
+        || self.report(client_id, Violation::TooManyFireBalls)
+    }
+
+    // true when the policy lets the event through anyway
+    fn report(&mut self, client_id: ClientId, violation: Violation) -> bool {
+        let count = self.limiter.0.entry(client_id)
+        .or_default()
+        .add_violation(self.fixed_tick.get(), self.config.violation_window_ticks);
+
+        self.violations.send(ClientViolation{
+            client_id,
+            violation,
+            count
+        });
+        self.config.policy == AbusePolicy::Log
+    }
+}
+
+pub struct RateLimitPlugin;
+
+impl Plugin for RateLimitPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<RateLimitConfig>()
+        .init_resource::<RateLimiter>()
+        .add_event::<ClientViolation>()
+        .add_systems(PostUpdate,
+            apply_abuse_policy_system
+            .before(ServerSet::Send)
+            .run_if(server_running)
+        );
+    }
+}
+
+fn apply_abuse_policy_system(
+    mut violations: EventReader<ClientViolation>,
+    mut limiter: ResMut<RateLimiter>,
+    mut server: ResMut<RenetServer>,
+    fixed_tick: Res<FixedTick>,
+    config: Res<RateLimitConfig>
+) {
+    for v in violations.read() {
+        if limiter.0.get_mut(&v.client_id)
+        .is_some_and(|s| s.should_log(fixed_tick.get(), config.violation_window_ticks)) {
+            warn!(
+                "client: {:?} violated: {:?} count: {} policy: {:?}",
+                v.client_id, v.violation, v.count, config.policy
+            );
+        }
+
+        if config.policy == AbusePolicy::Kick && v.count >= config.kick_violations {
+            warn!("client: {:?} kicked for abuse", v.client_id);
+            server.disconnect(renet::ClientId::from_raw(v.client_id.get()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket_allows_burst_then_refills() {
+        let config = TokenBucketConfig::new(2.0, PHYSICS_FIXED_TICK_RATE / 4.0);
+        let mut bucket = TokenBucket::default();
+
+        assert!(bucket.try_take(100, &config));
+        assert!(bucket.try_take(100, &config));
+        assert!(!bucket.try_take(100, &config));
+        // one token every 4 ticks
+        assert!(!bucket.try_take(103, &config));
+        assert!(bucket.try_take(104, &config));
+        // refill does not go over the burst
+        assert!(bucket.try_take(1000, &config));
+        assert!(bucket.try_take(1000, &config));
+        assert!(!bucket.try_take(1000, &config));
+    }
+
+    #[test]
+    fn violations_are_counted_within_window() {
+        let mut state = ClientLimitState::default();
+
+        assert_eq!(state.add_violation(10, 50), 1);
+        assert_eq!(state.add_violation(40, 50), 2);
+        assert_eq!(state.add_violation(40, 50), 3);
+        assert_eq!(state.add_violation(55, 50), 4);
+        // the ones at 10 and 40 left the window, steady violations never reset it
+        assert_eq!(state.add_violation(95, 50), 2);
+        assert_eq!(state.add_violation(200, 50), 1);
+
+        assert!(state.should_log(200, 50));
+        assert!(!state.should_log(249, 50));
+        assert!(state.should_log(250, 50));
+    }
+}